@@ -21,7 +21,7 @@ pub struct Asset {
     pub data: AssetType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct AssetManager {
     pub assets: Vec<Asset>,
 }
@@ -1,4 +1,4 @@
-use std::{error::Error, io};
+use std::{error::Error, io, path::PathBuf};
 
 pub type Result<T> = std::result::Result<T, ApplicationError>;
 
@@ -12,4 +12,19 @@ pub enum ApplicationError {
     PathError,
     #[error("NotFound")]
     NotFound,
+    #[error("Module {0} not found, tried:{}", list_paths(.1))]
+    ModuleNotFound(String, Vec<PathBuf>),
+    #[error("Lib {0} not found, tried:{}", list_paths(.1))]
+    LibNotFound(String, Vec<PathBuf>),
+    #[error("Plugin {0} not found")]
+    PluginNotFound(String),
+    #[error("tsconfig {0} not found")]
+    TsConfigNotFound(String),
+}
+
+fn list_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| format!("\n  {}", p.display()))
+        .collect()
 }
@@ -167,3 +167,135 @@ impl SliceExt for Vec<u8> {
         }
     }
 }
+
+/// Strip `//` and `/* */` comments, and trailing commas from jsonc (e.g. `tsconfig.json`)
+/// so it can be read as regular json
+pub fn strip_jsonc(jsonc: &str) -> String {
+    let mut output = String::with_capacity(jsonc.len());
+    let mut chars = jsonc.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            output.push(c);
+            match c {
+                '\\' => output.extend(chars.next()),
+                '"' => in_string = false,
+                _ => (),
+            }
+
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                output.push(c);
+            }
+
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        output.push(c);
+                        break;
+                    }
+                }
+            }
+
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+
+            (']' | '}', _) => {
+                let trimmed = output.trim_end().len();
+                if output[..trimmed].ends_with(',') {
+                    output.truncate(trimmed - 1);
+                }
+                output.push(c);
+            }
+
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// A temporary directory with `files` in it, removed again when dropped
+#[cfg(test)]
+pub struct TempDir(pub PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let dir = std::env::temp_dir().join(format!("trunk_bundler_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            let file = dir.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, contents).unwrap();
+        }
+
+        Self(fs::canonicalize(dir).unwrap())
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_comments() {
+        let jsonc = r#"{
+    // line comment
+    "a": 1, /* block
+    comment */ "b": "/* not a comment */",
+    "c": "// not a comment either"
+}"#;
+
+        let json: serde_json::Value = serde_json::from_str(&strip_jsonc(jsonc)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "a": 1,
+                "b": "/* not a comment */",
+                "c": "// not a comment either",
+            })
+        );
+    }
+
+    #[test]
+    fn strips_trailing_commas() {
+        let jsonc = r#"{ "a": [1, 2, ], "b": { "c": true, }, // done
+}"#;
+
+        let json: serde_json::Value = serde_json::from_str(&strip_jsonc(jsonc)).unwrap();
+        assert_eq!(json, serde_json::json!({ "a": [1, 2], "b": { "c": true } }));
+    }
+
+    #[test]
+    fn keeps_escaped_quotes_in_strings() {
+        let jsonc = r#"{ "a": "say \"hi\", // there", "b": "\\" }"#;
+
+        let json: serde_json::Value = serde_json::from_str(&strip_jsonc(jsonc)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "a": "say \"hi\", // there", "b": "\\" })
+        );
+    }
+}
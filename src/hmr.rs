@@ -0,0 +1,242 @@
+//! Long-running hot module reload server, started with `trunk_bundler hmr [index.html]`
+//!
+//! It watches every `<link data-bundler data-hmr>` project. When only react components changed,
+//! a hot update is pushed over a websocket to the client runtime injected into the page.
+//! Anything else gets a full reload through Trunk, by touching the html file so Trunk rebuilds.
+
+use std::{
+    fs,
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use glob::Pattern;
+use nipper::Document;
+use rustc_hash::FxHashMap;
+use swc_common::{FileName, SourceMap};
+use swc_ecma_ast::{EsVersion, Module};
+use swc_ecma_parser::{parse_file_as_module, EsConfig, Syntax, TsConfig};
+use tungstenite::{Message, WebSocket};
+use walkdir::WalkDir;
+
+use crate::{
+    errors::{ApplicationError, Result},
+    js_bundler::{exports_only_components, module_id, BundleBuilder, ModuleType, Workspace},
+    pipelines::{js_projects, JsProject},
+};
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+pub fn serve<P: AsRef<Path>>(html_file: P) -> Result<()> {
+    let html_file = html_file.as_ref();
+    let source_dir = html_file.parent().ok_or(ApplicationError::PathError)?;
+
+    let document = Document::from(&fs::read_to_string(html_file)?);
+    let selections = document
+        .select(r#"link[data-bundler][rel="js"][data-hmr]"#)
+        .iter()
+        .collect::<Vec<_>>();
+
+    let projects = js_projects(&selections, source_dir, true);
+    let Some(port) = projects.first().and_then(|p| p.hmr.as_ref()).map(|h| h.port) else {
+        return Err(ApplicationError::NotFound);
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("[hmr] listening on ws://127.0.0.1:{port}");
+
+    let clients = Clients::default();
+    {
+        let clients = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(ws) = tungstenite::accept(stream) {
+                    clients.lock().unwrap().push(ws);
+                }
+            }
+        });
+    }
+
+    // workspace packages are the project's own code too, so they're watched as well
+    let roots = projects
+        .iter()
+        .map(|p| {
+            let workspace = Workspace::find(&p.js_root).unwrap_or_default();
+            let packages = workspace.packages.into_iter().map(|(_, dir)| dir);
+            [p.js_root.clone()].into_iter().chain(packages).collect()
+        })
+        .collect::<Vec<Vec<_>>>();
+    let mut mtimes = roots.iter().map(|r| scan(r)).collect::<Vec<_>>();
+
+    loop {
+        thread::sleep(Duration::from_millis(200));
+
+        for ((project, roots), mtimes) in projects.iter().zip(&roots).zip(mtimes.iter_mut()) {
+            let current = scan(roots);
+            let changed = current
+                .iter()
+                .filter(|(path, mtime)| mtimes.get(*path) != Some(*mtime))
+                .map(|(path, _)| path.clone())
+                .chain(mtimes.keys().filter(|p| !current.contains_key(*p)).cloned())
+                .collect::<Vec<_>>();
+            // new files can match an `import.meta.glob`, so the modules globbing them need a rebuild
+            let added = changed.iter().any(|p| !mtimes.contains_key(p));
+            *mtimes = current;
+
+            if !changed.is_empty() {
+                update(project, &changed, added, html_file, &clients);
+            }
+        }
+    }
+}
+
+fn update(
+    project: &JsProject,
+    changed: &[PathBuf],
+    added: bool,
+    html_file: &Path,
+    clients: &Clients,
+) {
+    let files = changed
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>();
+
+    let accepted = !added && changed.iter().all(|p| is_refresh_boundary(project, p));
+    if !accepted {
+        broadcast(
+            clients,
+            serde_json::json!({ "type": "reload", "files": files }),
+        );
+
+        // rewriting the html file makes Trunk rebuild, and reload the page
+        if let Ok(html) = fs::read(html_file) {
+            let _ = fs::write(html_file, html);
+        }
+
+        return;
+    }
+
+    let mut config = project.config.clone();
+    // each changed module is its own es module, which the hmr client imports from a blob url
+    config.module_type = ModuleType::Es;
+    config.outputs = None;
+    config.banner = None;
+    config.footer = None;
+    config.source_maps = false;
+    config.external_modules = project.external_modules.clone();
+    config.hmr = project.hmr.clone();
+    config.node_shims = project.node_shims;
+    config.external_globals = project
+        .external_globals
+        .clone()
+        .map(|g| g.into_iter().collect());
+
+    // react-refresh swaps components through their families, so updated modules which import
+    // each other don't need to be evaluated in any particular order
+    let mut modules = vec![];
+    for path in changed {
+        // absolute paths, changes can be outside of the project root
+        let result = BundleBuilder::new(&project.js_root)
+            .entries([path.display().to_string()])
+            .config(config.clone())
+            .debug(true)
+            .hot_update()
+            .build();
+
+        match result {
+            Ok(output) => modules.push(serde_json::json!({
+                "id": module_id(&FileName::Real(path.clone())),
+                "code": String::from_utf8_lossy(&output.code),
+            })),
+
+            Err(e) => {
+                eprintln!("[hmr] {e}");
+                broadcast(
+                    clients,
+                    serde_json::json!({ "type": "error", "message": e.to_string() }),
+                );
+                return;
+            }
+        }
+    }
+
+    broadcast(
+        clients,
+        serde_json::json!({ "type": "update", "modules": modules, "files": files }),
+    );
+}
+
+/// Only modules which export nothing but components can be swapped by react-refresh. Entry modules
+/// usually render the app, so running them again would remount everything
+fn is_refresh_boundary(project: &JsProject, path: &Path) -> bool {
+    // modules are names, globs, or directories with an `index` module
+    let stem = path.with_extension("");
+    let is_entry = project.modules.iter().any(|module| {
+        let Ok(pattern) = Pattern::new(module) else {
+            return false;
+        };
+        let matches = |p: &Path| p == Path::new(module) || pattern.matches_path(p);
+
+        [project.js_root.clone(), project.js_root.join("src")]
+            .iter()
+            .filter_map(|base| stem.strip_prefix(base).ok())
+            .any(|relative| {
+                let is_index = relative.file_name().is_some_and(|n| n == "index");
+                matches(relative) || is_index && relative.parent().is_some_and(matches)
+            })
+    });
+
+    !is_entry && parse(path).is_some_and(|module| exports_only_components(&module))
+}
+
+/// A js or ts module, `None` for anything else, or when it doesn't parse
+fn parse(path: &Path) -> Option<Module> {
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    let syntax = match &*extension {
+        "js" | "jsx" | "mjs" | "cjs" => Syntax::Es(EsConfig {
+            jsx: true,
+            decorators: true,
+            decorators_before_export: true,
+            ..Default::default()
+        }),
+        "ts" | "tsx" | "mts" | "cts" => Syntax::Typescript(TsConfig {
+            tsx: extension == "tsx",
+            decorators: true,
+            ..Default::default()
+        }),
+        _ => return None,
+    };
+
+    let cm = SourceMap::default();
+    let fm = cm.new_source_file(
+        FileName::Real(path.to_owned()),
+        fs::read_to_string(path).ok()?,
+    );
+    parse_file_as_module(&fm, syntax, EsVersion::latest(), None, &mut vec![]).ok()
+}
+
+fn scan(roots: &[PathBuf]) -> FxHashMap<PathBuf, SystemTime> {
+    roots
+        .iter()
+        .flat_map(|root| {
+            WalkDir::new(root)
+                .into_iter()
+                .filter_entry(|e| e.file_name() != "node_modules")
+        })
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| Some((e.path().to_owned(), e.metadata().ok()?.modified().ok()?)))
+        .collect()
+}
+
+fn broadcast(clients: &Clients, message: serde_json::Value) {
+    let message = message.to_string();
+    clients
+        .lock()
+        .unwrap()
+        .retain_mut(|ws| ws.write_message(Message::Text(message.clone())).is_ok());
+}
@@ -1,7 +1,32 @@
+mod build_info;
+mod builder;
 mod bundler;
+mod chunks;
 mod configuration;
+mod external;
+mod external_globals;
 mod globals;
+mod import_glob;
+mod legal;
+mod libs;
 mod loading;
+mod mangle_props;
+mod name_cache;
+mod package;
+mod plugins;
+mod refresh;
+mod resolver;
+mod shims;
+mod side_effects;
+mod tsconfig;
+mod wasm_plugins;
+mod workspace;
 
+pub use builder::*;
 pub use bundler::*;
+pub use chunks::COMMON_CHUNK;
 pub use configuration::*;
+pub use plugins::*;
+pub use refresh::HMR_PRELUDE;
+pub(crate) use refresh::{exports_only_components, module_id};
+pub use workspace::Workspace;
@@ -0,0 +1,367 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use swc_common::collections::AHashMap;
+use toml::{Table, Value};
+
+use super::CargoOptions;
+
+/// What a build is made from, for the `banner` and `footer` template variables, and the build globals
+#[derive(Debug, Clone, Default)]
+pub struct BuildInfo {
+    /// `name` of the project's `package.json`
+    pub package: Option<String>,
+    /// `version` of the project's `package.json`
+    pub version: Option<String>,
+    /// Hash of the checked out git commit
+    pub commit: Option<String>,
+    /// UTC build time, like `2023-06-03T12:00:00Z`. `SOURCE_DATE_EPOCH` sets it, for reproducible builds
+    pub timestamp: String,
+    /// `debug` or `release`
+    pub profile: &'static str,
+    /// The Rust crate the app is built from
+    pub cargo: Option<CargoInfo>,
+}
+
+/// Name, version and enabled features of a crate
+#[derive(Debug, Clone, Default)]
+pub struct CargoInfo {
+    pub name: String,
+    pub version: String,
+    /// Sorted, with the features other features enable
+    pub features: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PackageJson {
+    name: Option<String>,
+    version: Option<String>,
+}
+
+impl BuildInfo {
+    pub fn new(root: &Path, debug: bool, cargo: &CargoOptions) -> Self {
+        // the closest `package.json`, as the project root can be a folder of a bigger package
+        let package = root.ancestors().find_map(|dir| {
+            let json = fs::read_to_string(dir.join("package.json")).ok()?;
+            serde_json::from_str::<PackageJson>(&json).ok()
+        });
+        let (package, version) = package.map_or((None, None), |p| (p.name, p.version));
+
+        let seconds = env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            });
+
+        Self {
+            package,
+            version,
+            commit: git_commit(root),
+            timestamp: format_timestamp(seconds),
+            profile: if debug { "debug" } else { "release" },
+            cargo: cargo_info(root, cargo),
+        }
+    }
+
+    /// `__APP_NAME__`, `__APP_VERSION__`, `__GIT_SHA__`, `__BUILD_TIME__` and `__CARGO_FEATURES__`,
+    /// as js expressions. The app is the Rust crate, or the `package.json` without one. Unknown values are `null`
+    pub fn defines(&self) -> AHashMap<String, String> {
+        let string = |v: Option<&str>| serde_json::to_string(&v).unwrap_or_default();
+        let (name, version) = match &self.cargo {
+            Some(cargo) => (Some(&*cargo.name), Some(&*cargo.version)),
+            None => (self.package.as_deref(), self.version.as_deref()),
+        };
+        let features = self
+            .cargo
+            .as_ref()
+            .map(|c| serde_json::to_string(&c.features).unwrap_or_default());
+
+        [
+            ("__APP_NAME__", string(name)),
+            ("__APP_VERSION__", string(version)),
+            ("__GIT_SHA__", string(self.commit.as_deref())),
+            ("__BUILD_TIME__", string(Some(&self.timestamp))),
+            ("__CARGO_FEATURES__", features.unwrap_or("[]".to_owned())),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect()
+    }
+
+    /// Replace `[package]`, `[version]`, `[commit]`, `[timestamp]` and `[profile]` in a template.
+    /// Unknown values are left empty
+    pub fn render(&self, template: &str) -> String {
+        let or_empty = |v: &Option<String>| v.clone().unwrap_or_default();
+        template
+            .replace("[package]", &or_empty(&self.package))
+            .replace("[version]", &or_empty(&self.version))
+            .replace("[commit]", &or_empty(&self.commit))
+            .replace("[timestamp]", &self.timestamp)
+            .replace("[profile]", self.profile)
+    }
+}
+
+/// Read the crate of `Cargo.toml`, and resolve the features it's built with like cargo does
+fn cargo_info(root: &Path, options: &CargoOptions) -> Option<CargoInfo> {
+    let manifest = match options.manifest.as_ref().map(|m| root.join(m)) {
+        Some(manifest) if manifest.is_dir() => manifest.join("Cargo.toml"),
+        Some(manifest) => manifest,
+        // workspace roots without a `[package]` are skipped
+        None => root
+            .ancestors()
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|file| read_toml(file).is_some_and(|t| t.contains_key("package")))?,
+    };
+    let toml = read_toml(&manifest)?;
+    let package = toml.get("package")?.as_table()?;
+
+    let name = package.get("name")?.as_str()?.to_owned();
+    let version = match package.get("version") {
+        Some(Value::String(version)) => version.clone(),
+        // `version.workspace = true` takes the version of the workspace's `Cargo.toml`
+        Some(Value::Table(_)) => workspace_version(&manifest).unwrap_or_default(),
+        _ => "0.0.0".to_owned(),
+    };
+
+    let table = toml
+        .get("features")
+        .and_then(Value::as_table)
+        .cloned()
+        .unwrap_or_default();
+    let mut features = if options.all_features {
+        table.keys().cloned().collect()
+    } else {
+        options.features.clone()
+    };
+    if !options.no_default_features && table.contains_key("default") {
+        features.push("default".to_owned());
+    }
+
+    // features enable the features they list, but not `dep:` ones or those of dependencies
+    let mut i = 0;
+    while i < features.len() {
+        let enabled = table.get(&features[i]).and_then(Value::as_array);
+        for feature in enabled.into_iter().flatten().filter_map(Value::as_str) {
+            if !feature.contains(['/', ':']) && !features.iter().any(|f| f == feature) {
+                features.push(feature.to_owned());
+            }
+        }
+        i += 1;
+    }
+    features.sort();
+    features.dedup();
+
+    Some(CargoInfo {
+        name,
+        version,
+        features,
+    })
+}
+
+fn workspace_version(manifest: &Path) -> Option<String> {
+    manifest.parent()?.ancestors().skip(1).find_map(|dir| {
+        let toml = read_toml(&dir.join("Cargo.toml"))?;
+        let version = toml.get("workspace")?.get("package")?.get("version")?;
+        Some(version.as_str()?.to_owned())
+    })
+}
+
+fn read_toml(file: &Path) -> Option<Table> {
+    toml::from_str(&fs::read_to_string(file).ok()?).ok()
+}
+
+/// The commit `HEAD` points to, read straight from the `.git` directory above `root`
+fn git_commit(root: &Path) -> Option<String> {
+    let (dir, dot_git) = root.ancestors().find_map(|dir| {
+        let dot_git = dir.join(".git");
+        dot_git.exists().then(|| (dir.to_owned(), dot_git))
+    })?;
+
+    // worktrees and submodules have a `.git` file pointing to their git directory
+    let git_dir = if dot_git.is_file() {
+        let file = fs::read_to_string(&dot_git).ok()?;
+        dir.join(file.trim().strip_prefix("gitdir:")?.trim())
+    } else {
+        dot_git
+    };
+    // worktrees share the refs of the main repository
+    let common_dir = fs::read_to_string(git_dir.join("commondir"))
+        .map(|common| git_dir.join(common.trim()))
+        .unwrap_or(git_dir.clone());
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let Some(reference) = head.trim().strip_prefix("ref:") else {
+        return Some(head.trim().to_owned());
+    };
+    let reference = reference.trim();
+
+    let candidates: [PathBuf; 2] = [git_dir.join(reference), common_dir.join(reference)];
+    if let Some(commit) = candidates
+        .iter()
+        .find_map(|file| fs::read_to_string(file).ok())
+    {
+        return Some(commit.trim().to_owned());
+    }
+
+    // refs which were packed by `git gc`
+    let packed = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| {
+        let (commit, name) = line.split_once(' ')?;
+        (name == reference).then(|| commit.to_owned())
+    })
+}
+
+/// Seconds since the unix epoch as an ISO 8601 UTC date and time
+fn format_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    const COMMIT: &str = "3f786850e387550fdab836ed7e6dc881de23001b";
+
+    #[test]
+    fn formats_timestamps_as_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        // leap days, in a year divisible by 400 too
+        assert_eq!(format_timestamp(1709210096), "2024-02-29T12:34:56Z");
+        assert_eq!(format_timestamp(951868799), "2000-02-29T23:59:59Z");
+        // `SOURCE_DATE_EPOCH=1685793600`
+        assert_eq!(format_timestamp(1685793600), "2023-06-03T12:00:00Z");
+    }
+
+    #[test]
+    fn reads_branch_commits_from_refs_and_packed_refs() {
+        let dir = TempDir::new(
+            "build_info_refs",
+            &[
+                (".git/HEAD", "ref: refs/heads/main\n"),
+                (".git/refs/heads/main", &format!("{COMMIT}\n")),
+                ("app/index.html", ""),
+            ],
+        );
+        assert_eq!(git_commit(&dir.0.join("app")).as_deref(), Some(COMMIT));
+
+        let dir = TempDir::new(
+            "build_info_packed_refs",
+            &[
+                (".git/HEAD", "ref: refs/heads/main\n"),
+                (
+                    ".git/packed-refs",
+                    &format!(
+                        "# pack-refs with: peeled fully-peeled sorted\n\
+                         0000000000000000000000000000000000000000 refs/heads/other\n\
+                         {COMMIT} refs/heads/main\n"
+                    ),
+                ),
+            ],
+        );
+        assert_eq!(git_commit(&dir.0).as_deref(), Some(COMMIT));
+    }
+
+    #[test]
+    fn reads_detached_heads() {
+        let dir = TempDir::new(
+            "build_info_detached",
+            &[(".git/HEAD", &format!("{COMMIT}\n"))],
+        );
+        assert_eq!(git_commit(&dir.0).as_deref(), Some(COMMIT));
+    }
+
+    #[test]
+    fn follows_the_git_file_of_worktrees() {
+        let dir = TempDir::new(
+            "build_info_worktree",
+            &[
+                ("repo/.git/HEAD", "ref: refs/heads/main\n"),
+                (
+                    "repo/.git/refs/heads/main",
+                    "0000000000000000000000000000000000000000\n",
+                ),
+                ("repo/.git/refs/heads/feature", &format!("{COMMIT}\n")),
+                (
+                    "repo/.git/worktrees/feature/HEAD",
+                    "ref: refs/heads/feature\n",
+                ),
+                ("repo/.git/worktrees/feature/commondir", "../..\n"),
+                ("feature/.git", "gitdir: ../repo/.git/worktrees/feature\n"),
+            ],
+        );
+        assert_eq!(git_commit(&dir.0.join("feature")).as_deref(), Some(COMMIT));
+    }
+
+    #[test]
+    fn resolves_enabled_features() {
+        let dir = TempDir::new(
+            "build_info_features",
+            &[(
+                "Cargo.toml",
+                r#"
+                [package]
+                name = "app"
+                version = "1.2.3"
+
+                [features]
+                default = ["router"]
+                router = ["history", "dep:url", "web-sys/History"]
+                history = []
+                ssr = ["history"]
+                "#,
+            )],
+        );
+        let features = |options: CargoOptions| cargo_info(&dir.0, &options).unwrap().features;
+
+        assert_eq!(
+            features(CargoOptions::default()),
+            ["default", "history", "router"]
+        );
+        assert_eq!(
+            features(CargoOptions {
+                features: vec!["ssr".to_owned()],
+                no_default_features: true,
+                ..Default::default()
+            }),
+            ["history", "ssr"]
+        );
+        assert_eq!(
+            features(CargoOptions {
+                all_features: true,
+                ..Default::default()
+            }),
+            ["default", "history", "router", "ssr"]
+        );
+
+        let info = cargo_info(&dir.0, &CargoOptions::default()).unwrap();
+        assert_eq!((&*info.name, &*info.version), ("app", "1.2.3"));
+    }
+}
@@ -0,0 +1,640 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use swc_atoms::JsWord;
+use swc_common::FileName;
+use swc_ecma_loader::{resolvers::node::NodeModulesResolver, TargetEnv};
+
+use super::{
+    bundler::bundle_js,
+    chunks::{Split, COMMON_CHUNK},
+    generate_config,
+    libs::Libs,
+    package::{PackageResolver, Packages},
+    refresh::{prelude_path, HMR_PRELUDE},
+    resolver::AliasResolver,
+    shims::NodeShimResolver,
+    tsconfig::TsConfigJson,
+    wasm_plugins::resolve_plugin,
+    OutputConfig, Plugin, SwcrcConfig,
+};
+
+use crate::errors::{ApplicationError, Result};
+
+/// The in-memory result of a bundle. Nothing is written to disk.
+#[derive(Debug, Clone)]
+pub struct BundleOutput {
+    /// The bundled js. With a `[name]` output, the common chunk
+    pub code: Vec<u8>,
+    /// Source map for `code`, if `SwcrcConfig::source_maps` was enabled
+    pub map: Option<Vec<u8>>,
+    /// The lib stylesheets, for `Manifest::css`
+    pub css: Option<Vec<u8>>,
+    /// Legal comments and npm packages of `code`, for its output with `.LICENSE.txt` appended.
+    /// Set with `SwcrcConfig::license_file` or `LegalComments::External`
+    pub license: Option<Vec<u8>>,
+    pub manifest: Manifest,
+    /// With a `[name]` output, the bundle of each entry, in the order of `Manifest::chunks`
+    pub chunks: Vec<Chunk>,
+}
+
+/// The bundle of a single entry
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub name: String,
+    pub code: Vec<u8>,
+    /// Source map, for the chunk's output with `.map` appended
+    pub map: Option<Vec<u8>>,
+    /// License file, for the chunk's output with `.LICENSE.txt` appended
+    pub license: Option<Vec<u8>>,
+}
+
+/// Describes what went into a bundle
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Manifest {
+    /// Output file name, relative to the output directory. A `[name]` in it gives each entry
+    /// its own file, and a `common` chunk with the modules they share
+    pub output: PathBuf,
+    /// Source map file name, relative to the output directory
+    pub map: Option<PathBuf>,
+    /// With a `[name]` output, the common chunk's file, if anything went into it
+    pub common: Option<PathBuf>,
+    /// With a `[name]` output, each entry's name and output file
+    pub chunks: Vec<(String, PathBuf)>,
+    /// Entry module names, and the files they resolved to
+    pub entries: Vec<(String, PathBuf)>,
+    /// Every source file which was loaded into the bundle
+    pub modules: Vec<PathBuf>,
+    /// External lib files prepended to the bundle
+    pub libs: Vec<PathBuf>,
+    /// Lib stylesheets, bundled into `css`
+    pub styles: Vec<PathBuf>,
+    /// Css file name, relative to the output directory, when there are lib stylesheets
+    pub css: Option<PathBuf>,
+}
+
+/// Builds a js bundle from a project root
+///
+/// `root` is laid out the same way as a `data-bundler` project (`src`, `lib`, `node_modules`).
+pub struct BundleBuilder {
+    root: PathBuf,
+    entries: Option<Vec<String>>,
+    config: Option<SwcrcConfig>,
+    debug: bool,
+    hook: Option<Box<dyn swc_bundler::Hook>>,
+    plugins: Vec<Arc<dyn Plugin>>,
+    hot_update: bool,
+}
+
+impl BundleBuilder {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+            entries: None,
+            config: None,
+            debug: false,
+            hook: None,
+            plugins: Vec::new(),
+            hot_update: false,
+        }
+    }
+
+    /// Entry module names, looked up in `{root,src}/{name}.{js,jsx,ts,tsx,mjs,cjs,mts,cts}`, directories,
+    /// globs, or file paths relative to `root`.
+    /// Overrides `SwcrcConfig::modules`. Without any, the root `package.json` `module`/`main`, or an `index` module is the entry
+    pub fn entries<I, S>(mut self, entries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.entries = Some(entries.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Defaults to `generate_config(debug)`
+    pub fn config(mut self, config: SwcrcConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Custom `swc_bundler` hook, used for `import.meta` props
+    pub fn hook(mut self, hook: Box<dyn swc_bundler::Hook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Register a plugin. Plugins run in the order they were registered
+    pub fn plugin<T: Plugin + 'static>(mut self, plugin: T) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        self
+    }
+
+    /// Build a hot update for the hmr server. Only contains the entries, which import everything
+    /// else from the hmr registry of the page, without lib files or the hmr prelude
+    pub fn hot_update(mut self) -> Self {
+        self.hot_update = true;
+        self
+    }
+
+    pub fn build(self) -> Result<BundleOutput> {
+        let debug = self.debug;
+        let root = &*self.root;
+        if !root.is_dir() {
+            return Err(ApplicationError::PathError);
+        }
+
+        let mut config = self.config.unwrap_or_else(|| generate_config(debug));
+        if let Some(entries) = self.entries {
+            config.modules = entries;
+        }
+
+        let tsconfig = TsConfigJson::load(root)?;
+        if let Some(tsconfig) = &tsconfig {
+            config.jsx = Some(tsconfig.jsx_options(config.jsx.take().unwrap_or_default()));
+        }
+
+        let packages = Arc::<Packages>::default();
+        let resolver = AliasResolver::new(
+            NodeShimResolver::new(
+                PackageResolver::new(
+                    NodeModulesResolver::new(
+                        TargetEnv::Browser,
+                        Default::default(),
+                        config.preserve_symlinks,
+                    ),
+                    packages.clone(),
+                    debug,
+                    config.conditions.take(),
+                    config.preserve_symlinks,
+                ),
+                config.node_shims,
+            ),
+            root,
+            config.alias.take(),
+            tsconfig.as_ref(),
+        );
+
+        if let Some(plugins) = &mut config.plugins {
+            for plugin in plugins {
+                plugin.0 = resolve_plugin(root, &plugin.0)?;
+            }
+        }
+
+        // set default config outputs if non-existent
+        if config.output.is_none() {
+            config.output = Some(PathBuf::from(if debug { "dist.js" } else { "dist.min.js" }));
+        }
+
+        let libs = if self.hot_update {
+            Libs::default()
+        } else {
+            Libs::find(&root.join("lib"), config.libs.as_deref())?
+        };
+
+        let mut entries = Vec::new();
+
+        if debug && config.hmr.is_some() && !self.hot_update {
+            entries.push((HMR_PRELUDE.to_owned(), prelude_path(root)));
+        }
+
+        let output = config.output.clone().unwrap();
+        let template = output.display().to_string();
+
+        // grouped outputs, or a `[name]` output, split the entries into several files
+        let split = match config.outputs.take() {
+            Some(outputs) => {
+                let mut chunks = vec![];
+                for OutputConfig { output, modules } in outputs {
+                    let mut chunk_entries = vec![];
+                    for module in &modules {
+                        chunk_entries.extend(find_entries(root, module)?);
+                    }
+                    if modules.is_empty() {
+                        chunk_entries.push(default_entry(root)?);
+                    }
+
+                    entries.extend(chunk_entries.iter().cloned());
+                    chunks.push((
+                        output.display().to_string(),
+                        output,
+                        to_file_names(chunk_entries),
+                    ));
+                }
+
+                Some(Split {
+                    chunks,
+                    common: output.clone(),
+                })
+            }
+
+            None => {
+                let mut module_entries = vec![];
+                for module in &config.modules {
+                    module_entries.extend(find_entries(root, module)?);
+                }
+                if config.modules.is_empty() {
+                    module_entries.push(default_entry(root)?);
+                }
+                entries.extend(module_entries.iter().cloned());
+
+                if template.contains("[name]") && !self.hot_update {
+                    if module_entries.iter().any(|(name, _)| name == COMMON_CHUNK) {
+                        return Err(ApplicationError::GeneralError(format!(
+                            "`{COMMON_CHUNK}` can't be an entry name with a `[name]` output, it's the name of the shared chunk"
+                        )
+                        .into()));
+                    }
+
+                    let chunks = module_entries
+                        .into_iter()
+                        .map(|(name, file)| {
+                            let output = PathBuf::from(template.replace("[name]", &name));
+                            (name.clone(), output, to_file_names(vec![(name, file)]))
+                        })
+                        .collect();
+
+                    Some(Split {
+                        chunks,
+                        common: PathBuf::from(template.replace("[name]", COMMON_CHUNK)),
+                    })
+                } else {
+                    None
+                }
+            }
+        };
+
+        // split entries are bundled by chunk, this only leaves the hmr prelude
+        let bundled = entries
+            .iter()
+            .filter(|(name, _)| split.is_none() || name == HMR_PRELUDE)
+            .cloned()
+            .collect();
+
+        let mut external_modules = vec![];
+        if let Some(modules) = &config.external_modules {
+            for module in modules {
+                external_modules.push(JsWord::from(module.clone()));
+            }
+        }
+
+        let map = config.source_maps.then(|| {
+            let mut map = output.clone().into_os_string();
+            map.push(".map");
+            PathBuf::from(map)
+        });
+
+        let manifest = Manifest {
+            output,
+            map,
+            common: None,
+            chunks: vec![],
+            entries,
+            modules: vec![],
+            libs: libs.js,
+            styles: libs.css,
+            css: None,
+        };
+
+        bundle_js(
+            debug,
+            config,
+            root,
+            to_file_names(bundled),
+            split,
+            external_modules,
+            resolver,
+            packages,
+            manifest,
+            self.hook,
+            self.plugins,
+            self.hot_update,
+        )
+    }
+}
+
+fn to_file_names(entries: Vec<(String, PathBuf)>) -> Vec<(String, FileName)> {
+    entries
+        .into_iter()
+        .map(|(name, file)| (name, FileName::Real(file)))
+        .collect()
+}
+
+/// Extensions entry modules are looked up with, in order
+const EXTENSIONS: &[&str] = &["js", "jsx", "ts", "tsx", "mjs", "cjs", "mts", "cts"];
+
+/// Entry files for a module name, looked up in the root dir first, then the src dir.
+/// A name can be a file with or without its extension, a directory with a `package.json`
+/// `module`/`main` field or an `index` module, or a glob matching any number of files
+fn find_entries(root: &Path, module: &str) -> Result<Vec<(String, PathBuf)>> {
+    let bases = [root.to_owned(), root.join("src")];
+    let mut tried = vec![];
+
+    if module.contains(['*', '?', '[']) {
+        for base in &bases {
+            let pattern = base.join(module);
+            tried.push(pattern.clone());
+
+            let files = glob::glob(&pattern.display().to_string())
+                .map_err(|e| ApplicationError::GeneralError(e.msg.into()))?
+                .flatten()
+                .filter(|f| f.is_file() && has_entry_extension(f));
+
+            // entries are named after their path, so `pages/*` gives `pages/home`, `pages/about`, ...
+            let entries = files
+                .filter_map(|file| {
+                    let name = file.strip_prefix(base).ok()?.with_extension("");
+                    let name = name.to_string_lossy().replace('\\', "/");
+                    Some((name, file))
+                })
+                .collect::<Vec<_>>();
+
+            if !entries.is_empty() {
+                return Ok(entries);
+            }
+        }
+
+        return Err(ApplicationError::ModuleNotFound(module.to_owned(), tried));
+    }
+
+    for base in &bases {
+        let path = base.join(module);
+
+        let mut candidates = vec![path.clone()];
+        candidates.extend(EXTENSIONS.iter().map(|e| with_extension(&path, e)));
+        if path.is_dir() {
+            candidates.extend(package_entries(&path));
+            candidates.extend(EXTENSIONS.iter().map(|e| path.join(format!("index.{e}"))));
+        }
+
+        for candidate in candidates {
+            if candidate.is_file() {
+                return Ok(vec![(module.to_owned(), candidate)]);
+            }
+            tried.push(candidate);
+        }
+    }
+
+    Err(ApplicationError::ModuleNotFound(module.to_owned(), tried))
+}
+
+/// The entry of a project without modules: the `module`/`main` field of the root `package.json`,
+/// or else an `index` module. It's named after its file
+fn default_entry(root: &Path) -> Result<(String, PathBuf)> {
+    let mut tried = vec![];
+    for entry in package_entries(root) {
+        if entry.is_file() {
+            let name = entry.file_stem().unwrap_or_default().to_string_lossy();
+            return Ok((name.into_owned(), entry));
+        }
+        tried.push(entry);
+    }
+
+    match find_entries(root, "index") {
+        Ok(mut entries) => Ok(entries.remove(0)),
+        Err(ApplicationError::ModuleNotFound(name, paths)) => {
+            tried.extend(paths);
+            Err(ApplicationError::ModuleNotFound(name, tried))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The `module` and `main` fields of a directory's `package.json`
+fn package_entries(dir: &Path) -> Vec<PathBuf> {
+    let json = fs::read_to_string(dir.join("package.json"))
+        .ok()
+        .and_then(|json| serde_json::from_str::<Value>(&json).ok());
+
+    let Some(json) = json else {
+        return vec![];
+    };
+
+    ["module", "main"]
+        .iter()
+        .filter_map(|field| json.get(field)?.as_str())
+        // `./lib/main.js` as `lib/main.js`, `components()` skips the `.`
+        .map(|entry| dir.join(entry).components().collect())
+        .collect()
+}
+
+fn has_entry_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| EXTENSIONS.iter().any(|ext| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Appends an extension, as `Path::with_extension` would replace the one in `name.config`
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.to_owned().into_os_string();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    fn names(entries: Vec<(String, PathBuf)>, root: &Path) -> Vec<(String, String)> {
+        entries
+            .into_iter()
+            .map(|(name, file)| {
+                let file = file.strip_prefix(root).unwrap().to_string_lossy();
+                (name, file.replace('\\', "/"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_files_directories_and_globs() {
+        let dir = TempDir::new(
+            "builder_entries",
+            &[
+                ("src/app.tsx", ""),
+                ("src/worker.mts", ""),
+                ("src/admin/index.js", ""),
+                ("src/widget/package.json", r#"{ "main": "./lib/main.js" }"#),
+                ("src/widget/lib/main.js", ""),
+                ("src/pages/home.jsx", ""),
+                ("src/pages/about.ts", ""),
+                ("src/pages/notes.md", ""),
+            ],
+        );
+        let root = &dir.0;
+        let find = |module| names(find_entries(root, module).unwrap(), root);
+
+        assert_eq!(find("app"), [("app".into(), "src/app.tsx".into())]);
+        assert_eq!(find("worker"), [("worker".into(), "src/worker.mts".into())]);
+        assert_eq!(
+            find("admin"),
+            [("admin".into(), "src/admin/index.js".into())]
+        );
+        assert_eq!(
+            find("widget"),
+            [("widget".into(), "src/widget/lib/main.js".into())]
+        );
+        assert_eq!(
+            find("pages/*"),
+            [
+                ("pages/about".into(), "src/pages/about.ts".into()),
+                ("pages/home".into(), "src/pages/home.jsx".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_every_tried_path() {
+        let dir = TempDir::new("builder_missing", &[]);
+
+        let Err(ApplicationError::ModuleNotFound(name, tried)) = find_entries(&dir.0, "app")
+        else {
+            panic!("`app` shouldn't be found");
+        };
+        assert_eq!(name, "app");
+        assert_eq!(tried.len(), 2 * (EXTENSIONS.len() + 1));
+        assert!(tried.contains(&dir.0.join("src/app.cts")));
+    }
+
+    #[test]
+    fn the_root_package_is_the_default_entry() {
+        let dir = TempDir::new(
+            "builder_default_package",
+            &[
+                (
+                    "package.json",
+                    r#"{ "module": "src/main.js", "main": "dist/main.cjs" }"#,
+                ),
+                ("src/main.js", ""),
+                ("src/index.js", ""),
+            ],
+        );
+
+        let entry = default_entry(&dir.0).unwrap();
+        assert_eq!(entry, ("main".to_owned(), dir.0.join("src/main.js")));
+    }
+
+    #[test]
+    fn an_index_module_is_the_default_entry_without_a_package() {
+        let dir = TempDir::new(
+            "builder_default_index",
+            &[
+                ("package.json", r#"{ "name": "app" }"#),
+                ("src/index.ts", ""),
+            ],
+        );
+
+        let entry = default_entry(&dir.0).unwrap();
+        assert_eq!(entry, ("index".to_owned(), dir.0.join("src/index.ts")));
+    }
+
+    #[test]
+    fn a_missing_default_entry_lists_the_package_entry() {
+        let dir = TempDir::new(
+            "builder_default_missing",
+            &[("package.json", r#"{ "main": "dist/app.js" }"#)],
+        );
+
+        let Err(ApplicationError::ModuleNotFound(_, tried)) = default_entry(&dir.0) else {
+            panic!("there's no default entry");
+        };
+        assert_eq!(tried[0], dir.0.join("dist/app.js"));
+        assert!(tried.contains(&dir.0.join("src/index.js")));
+    }
+
+    #[test]
+    fn builds_are_the_same_every_time() {
+        let entries = (0..8)
+            .map(|i| {
+                format!(
+                    "import {{ name, shared }} from './shared';
+                    import * as ns from './ns{}';
+                    const cjs = require('./cjs');
+                    const name$1 = 'entry{i}';
+                    function shared$1() {{ return name$1; }}
+                    console.log(name, shared(), shared$1(), ns, cjs);",
+                    i % 2
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut files = entries
+            .iter()
+            .enumerate()
+            .map(|(i, code)| (format!("src/entry{i}.js"), code.as_str()))
+            .collect::<Vec<_>>();
+        files.extend([
+            (
+                "src/shared.js".to_owned(),
+                "export const name = 'shared'; export function shared() { return name; }",
+            ),
+            ("src/ns0.js".to_owned(), "export const name = 'ns0';"),
+            ("src/ns1.js".to_owned(), "export * from './shared';"),
+            ("src/cjs.js".to_owned(), "exports.name = 'cjs';"),
+        ]);
+        let files = files
+            .iter()
+            .map(|(path, code)| (path.as_str(), *code))
+            .collect::<Vec<_>>();
+        let dir = TempDir::new("builder_reproducible", &files);
+
+        let build = || {
+            let output = BundleBuilder::new(&dir.0)
+                .entries((0..8).map(|i| format!("entry{i}")))
+                .config(crate::js_bundler::generate_config(false))
+                .build()
+                .unwrap();
+            String::from_utf8(output.code).unwrap()
+        };
+
+        let first = build();
+        for _ in 0..4 {
+            assert_eq!(build(), first);
+        }
+    }
+
+    #[test]
+    fn iife_chunks_read_live_bindings_of_the_common_chunk() {
+        let dir = TempDir::new(
+            "builder_live_bindings",
+            &[
+                (
+                    "src/a.js",
+                    "import { count, inc } from './counter'; inc(); console.log(count);",
+                ),
+                (
+                    "src/b.js",
+                    "import { count } from './counter'; console.log(count);",
+                ),
+                (
+                    "src/counter.js",
+                    "export let count = 0; export function inc() { count++; }",
+                ),
+            ],
+        );
+        let mut config = crate::js_bundler::generate_config(true);
+        config.output = Some("[name].js".into());
+
+        let output = BundleBuilder::new(&*dir.0)
+            .entries(["a", "b"])
+            .config(config)
+            .build()
+            .unwrap();
+        let a = String::from_utf8(output.chunks[0].code.clone()).unwrap();
+
+        // `count` is read from the common chunk after `inc()`, not copied out of it before
+        let inc = a.find("window.__trunk_common_common.__m0_1()").unwrap();
+        let count = a
+            .find("console.log(window.__trunk_common_common.__m0_0)")
+            .unwrap();
+        assert!(inc < count, "{a}");
+    }
+}
@@ -1,31 +1,41 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     error::Error,
-    io::Read,
+    fs,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
 use swc_atoms::JsWord;
-use swc_bundler::Bundler;
-use swc_common::{FileName, Mark, SourceMap, GLOBALS};
+use swc_bundler::{Bundle, Bundler, Load, ModuleRecord};
+use swc_common::{FileName, Globals, Mark, SourceMap, Span, GLOBALS};
+use swc_ecma_ast::{EsVersion, KeyValueProp, Program};
 use swc_ecma_codegen::{
     text_writer::{omit_trailing_semi, JsWriter, WriteJs},
     Emitter,
 };
-use swc_ecma_loader::{
-    resolvers::{lru::CachingResolver, node::NodeModulesResolver},
-    TargetEnv,
-};
-use swc_ecma_minifier::option::ExtraOptions;
-use swc_ecma_transforms::fixer;
+use swc_ecma_loader::{resolve::Resolve, resolvers::lru::CachingResolver};
+use swc_ecma_minifier::option::{ExtraOptions, ManglePropertiesOptions};
+use swc_ecma_parser::{parse_file_as_script, Syntax};
+use swc_ecma_transforms::{fixer, resolver};
 use swc_ecma_visit::VisitMutWith;
-
-use walkdir::WalkDir;
+use swc_node_comments::SwcComments;
 
 use super::{
-    loading::{Hook, Loader},
-    SwcrcConfig,
+    build_info::BuildInfo,
+    chunks::{ChunkLoader, ChunkResolver, Chunks, Split, COMMON_CHUNK},
+    external::{chain_loader_maps, LoaderMaps},
+    import_glob::relative,
+    legal::{is_legal, to_source, Notices},
+    loading::{Hook, Loader, SourcePaths},
+    mangle_props::mangle_props,
+    name_cache::NameCache,
+    package::Packages,
+    resolver::ProjectResolver,
+    wasm_plugins::load_plugins,
+    BundleBuilder, BundleOutput, Chunk, LegalComments, Manifest, MinifyLevel, Plugin, SwcrcConfig,
+    HMR_PRELUDE,
 };
 
 use crate::{
@@ -34,229 +44,780 @@ use crate::{
     helpers::SliceExt,
 };
 
-fn find_first<P: AsRef<Path>>(paths: &[P]) -> Result<PathBuf> {
-    let mut result = None;
-    for path in paths {
-        let path = path.as_ref();
-        if path.is_file() {
-            result = Some(path.to_owned());
-            break;
-        }
-    }
-
-    result.ok_or(ApplicationError::NotFound)
-}
-
+/// Bundle a project into the asset manager. Returns the bundle's manifest, to link its files
 pub fn compile_js<P: AsRef<Path>>(
     root: P,
     output_dir: P,
-    mut config: SwcrcConfig,
+    config: SwcrcConfig,
     asset_manager: &Mutex<AssetManager>,
     debug: bool,
     hook: Option<Box<dyn swc_bundler::Hook>>,
-) -> Result<()> {
+) -> Result<Manifest> {
     let root = root.as_ref();
-    assert!(
-        root.is_dir(),
-        "root path `{}` does not exist, or is not a directory",
-        root.display()
-    );
+    if !root.is_dir() {
+        return Err(ApplicationError::PathError);
+    }
 
     // all needed directories get created on asset dump
     let output_dir = output_dir.as_ref();
 
-    // set default config outputs if non-existent
-    if config.output.is_none() {
-        config.output = Some(PathBuf::from(if debug { "dist.js" } else { "dist.min.js" }));
+    let mut builder = BundleBuilder::new(root).config(config).debug(debug);
+    if let Some(hook) = hook {
+        builder = builder.hook(hook);
     }
 
-    //
-    // Push external lib folders files for adding to output file
-    //
-    let mut external_libs = vec![];
+    let BundleOutput {
+        code,
+        map,
+        css,
+        license,
+        manifest,
+        chunks,
+    } = builder.build()?;
 
-    let libs_path = root.join("lib");
+    let mut asset_manager = asset_manager.lock().unwrap();
 
-    for entry in WalkDir::new(&libs_path).into_iter().flatten() {
-        let p = entry.path();
-        let is_file = p.is_file();
+    if let (Some(map), Some(map_file)) = (map, &manifest.map) {
+        asset_manager.add(AssetType::Memory(map), output_dir.join(map_file));
+    }
 
-        if is_file && p.extension().is_some_and(|e| e == "js") {
-            external_libs.push(p.to_owned());
-        }
+    if let (Some(css), Some(css_file)) = (css, &manifest.css) {
+        asset_manager.add(AssetType::Memory(css), output_dir.join(css_file));
     }
-    //
-    // End
-    //
-
-    let src_dir = root.join("src");
-
-    let mut entries = HashMap::new();
-
-    for module in config.modules.iter().map(|n| &**n) {
-        // first try root dir, then src dir. Both paths are valid for a module path
-        let file = find_first(&[
-            root.join(&format!("{module}.js")),
-            root.join(&format!("{module}.jsx")),
-            root.join(&format!("{module}.ts")),
-            root.join(&format!("{module}.tsx")),
-            src_dir.join(&format!("{module}.js")),
-            src_dir.join(&format!("{module}.jsx")),
-            src_dir.join(&format!("{module}.ts")),
-            src_dir.join(&format!("{module}.tsx")),
-        ])
-        .unwrap_or_else(|_| panic!("Module {module} not found"));
-
-        entries.insert(module.to_owned(), FileName::Real(file));
+
+    // split bundles only write the common chunk when there's something in it
+    let output = match (&manifest.common, manifest.chunks.is_empty()) {
+        (Some(common), false) => Some(common),
+        (None, false) => None,
+        (_, true) => Some(&manifest.output),
+    };
+    if let Some(output) = output {
+        if let Some(license) = license {
+            asset_manager.add(
+                AssetType::Memory(license),
+                output_dir.join(license_file(output)),
+            );
+        }
+
+        asset_manager.add(AssetType::Memory(code), output_dir.join(output));
     }
 
-    let mut external_modules = vec![];
-    if let Some(modules) = &config.external_modules {
-        for module in modules {
-            external_modules.push(JsWord::from(module.clone()));
+    for (chunk, (_, output)) in chunks.into_iter().zip(&manifest.chunks) {
+        if let Some(map) = chunk.map {
+            let mut map_file = output.clone().into_os_string();
+            map_file.push(".map");
+            asset_manager.add(AssetType::Memory(map), output_dir.join(map_file));
+        }
+
+        if let Some(license) = chunk.license {
+            asset_manager.add(
+                AssetType::Memory(license),
+                output_dir.join(license_file(output)),
+            );
         }
+
+        asset_manager.add(AssetType::Memory(chunk.code), output_dir.join(output));
     }
 
-    let output_bundle_js = output_dir.join(config.output.as_ref().unwrap());
+    Ok(manifest)
+}
 
-    bundle_js(
-        debug,
-        config,
-        entries,
-        external_modules,
-        external_libs,
-        asset_manager,
-        output_bundle_js,
-        hook,
-    )
+/// `dist.min.js.LICENSE.txt` for `dist.min.js`
+fn license_file(output: &Path) -> PathBuf {
+    let mut file = output.to_owned().into_os_string();
+    file.push(".LICENSE.txt");
+    PathBuf::from(file)
 }
 
 #[allow(clippy::too_many_arguments)]
-fn bundle_js(
+pub(super) fn bundle_js(
     debug: bool,
     mut swc_config: SwcrcConfig,
-    entries: HashMap<String, FileName>,
+    root: &Path,
+    entries: Vec<(String, FileName)>,
+    split: Option<Split>,
     external_modules: Vec<JsWord>,
-    external_libs: Vec<PathBuf>,
-    asset_manager: &Mutex<AssetManager>,
-    output_bundle_js: PathBuf,
+    resolver: ProjectResolver,
+    packages: Arc<Packages>,
+    mut manifest: Manifest,
     hook: Option<Box<dyn swc_bundler::Hook>>,
-) -> Result<()> {
+    plugins: Vec<Arc<dyn Plugin>>,
+    hot_update: bool,
+) -> Result<BundleOutput> {
     let cm = Arc::<SourceMap>::default();
 
-    let minify_cfg = swc_config.minify;
-    let minify = swc_config.config.minify;
-
-    let globals = Box::default();
-    let mut bundler = Bundler::new(
-        &globals,
-        cm.clone(),
-        Loader {
-            cm: cm.clone(),
-            env: swc_config.env.take(),
-            vars: swc_config.vars.take(),
-            typeofs: swc_config.typeofs.take(),
-            debug,
-        },
-        CachingResolver::new(
-            4096,
-            NodeModulesResolver::new(TargetEnv::Browser, Default::default(), true),
-        ),
-        swc_bundler::Config {
-            require: true,
-            disable_inliner: !swc_config.inline,
-            external_modules,
-            disable_fixer: minify,
-            disable_hygiene: minify,
-            disable_dce: false,
-            module: swc_config.module_type.into(),
-        },
-        hook.unwrap_or(Box::new(Hook)),
-    );
-
-    let mut modules = bundler
-        .bundle(entries)
+    if swc_config.minify_level == MinifyLevel::None {
+        swc_config.config.minify = false;
+    }
+
+    if let Some(plugins) = &swc_config.plugins {
+        load_plugins(plugins, &swc_config.cache_root)?;
+    }
+
+    let hmr_prelude = entries
+        .iter()
+        .find(|(name, _)| name == HMR_PRELUDE)
+        .map(|(_, f)| PathBuf::from(f.to_string()));
+    let refresh = debug && swc_config.hmr.is_some();
+
+    let resolver = Arc::new(CachingResolver::new(4096, resolver));
+    let comments = SwcComments::default();
+    let legal_comments = SwcComments::default();
+    let loader_maps = LoaderMaps::default();
+
+    let build_info = BuildInfo::new(root, debug, &swc_config.cargo);
+    let banner = swc_config.banner.as_deref().map(|b| build_info.render(b));
+    let footer = swc_config.footer.as_deref().map(|f| build_info.render(f));
+
+    let loader = Loader {
+        cm: cm.clone(),
+        env: swc_config.env.take(),
+        vars: swc_config.vars.take(),
+        typeofs: swc_config.typeofs.take(),
+        build_defines: build_info.defines(),
+        debug,
+        plugins: plugins.clone(),
+        wasm_plugins: swc_config.plugins.take(),
+        loaders: swc_config.loaders.take(),
+        loader_maps: loader_maps.clone(),
+        jsx: swc_config.jsx.take().unwrap_or_default(),
+        refresh,
+        hot_update,
+        hmr_prelude,
+        node_shims: swc_config.node_shims,
+        external_globals: swc_config.external_globals.take(),
+        external_modules: external_modules.clone(),
+        resolver: resolver.clone(),
+        packages: packages.clone(),
+        reexports: Default::default(),
+        comments: comments.clone(),
+        legal_comments: legal_comments.clone(),
+    };
+
+    // only release builds mangle names
+    let name_cache = match &swc_config.name_cache {
+        Some(file) if swc_config.config.minify && swc_config.minify_level == MinifyLevel::Full => {
+            Some(NameCache::load(&root.join(file))?)
+        }
+        _ => None,
+    };
+
+    let globals = Globals::default();
+    let paths = SourcePaths::new(root);
+    let hook = hook.unwrap_or(Box::new(Hook {
+        paths: paths.clone(),
+    }));
+    let context = Context {
+        config: &swc_config,
+        cm: cm.clone(),
+        globals: &globals,
+        resolver,
+        hook: &*hook,
+        comments,
+        legal_comments,
+        loader_maps,
+        packages,
+        plugins,
+        paths,
+        banner,
+        footer,
+        name_cache,
+        next_names: Default::default(),
+    };
+
+    let Some(split) = split else {
+        let mut modules =
+            context.bundle(&loader, context.resolver.clone(), entries, external_modules)?;
+        context.mangle_props(modules.iter_mut().collect())?;
+        let emitted = context.emit(&modules, &manifest.libs, manifest.map.as_deref())?;
+        let css = bundle_styles(&manifest.styles)?;
+
+        manifest.css = css.as_ref().map(|_| manifest.output.with_extension("css"));
+        manifest.modules = loaded_modules(&cm);
+        context.save_name_cache(root)?;
+        return Ok(BundleOutput {
+            code: emitted.code,
+            map: emitted.map,
+            css,
+            license: emitted.license,
+            manifest,
+            chunks: vec![],
+        });
+    };
+
+    let map_of = |output: &Path| {
+        swc_config.source_maps.then(|| {
+            let mut map = output.to_owned().into_os_string();
+            map.push(".map");
+            PathBuf::from(map)
+        })
+    };
+
+    let chunks = GLOBALS
+        .set(&globals, || {
+            Chunks::new(
+                &loader,
+                &*context.resolver,
+                &split.chunks,
+                &split.common,
+                &external_modules,
+                swc_config.module_type,
+            )
+        })
         .map_err(Into::<Box<dyn Error>>::into)?;
 
-    if minify {
-        modules = modules
-            .into_iter()
-            .map(|mut b| {
-                let cm = cm.clone();
-
-                GLOBALS.set(&globals, || {
-                    b.module = swc_ecma_minifier::optimize(
-                        b.module.into(),
-                        cm,
-                        None,
-                        None,
-                        &minify_cfg,
-                        &ExtraOptions {
-                            unresolved_mark: Mark::new(),
-                            top_level_mark: Mark::new(),
-                        },
-                    )
-                    .expect_module();
-                    b.module.visit_mut_with(&mut fixer(None));
-                    b
+    let common = split.common;
+    let mut bundles = vec![];
+    for (name, output, chunk_entries) in split.chunks {
+        let dir = output.parent().unwrap_or(Path::new(""));
+        let specifier = chunks.specifier(&relative(dir, &common));
+
+        let mut external_modules = external_modules.clone();
+        external_modules.push(specifier.clone().into());
+
+        let loader = ChunkLoader {
+            loader: &loader,
+            chunks: &chunks,
+            specifier: Some(&specifier),
+        };
+        let modules = context.bundle(
+            loader,
+            context.resolver.clone(),
+            chunk_entries,
+            external_modules,
+        )?;
+        bundles.push((name, output, modules));
+    }
+
+    // the common chunk also gets the lib files and the hmr prelude, which run before any entry
+    let mut common_entries = entries;
+    if !chunks.is_empty() {
+        common_entries.push((COMMON_CHUNK.to_owned(), chunks.common_module()));
+    }
+
+    let mut modules = if common_entries.is_empty() {
+        vec![]
+    } else {
+        let loader = ChunkLoader {
+            loader: &loader,
+            chunks: &chunks,
+            specifier: None,
+        };
+        let resolver = ChunkResolver {
+            inner: context.resolver.clone(),
+        };
+        context.bundle(loader, resolver, common_entries, external_modules)?
+    };
+
+    context.mangle_props(
+        bundles
+            .iter_mut()
+            .flat_map(|(_, _, modules)| modules.iter_mut())
+            .chain(modules.iter_mut())
+            .collect(),
+    )?;
+
+    let mut outputs = vec![];
+    for (name, output, modules) in bundles {
+        let map = map_of(&output);
+        let emitted = context.emit(&modules, &[], map.as_deref())?;
+        manifest.chunks.push((name.clone(), output));
+        outputs.push(Chunk {
+            name,
+            code: emitted.code,
+            map: emitted.map,
+            license: emitted.license,
+        });
+    }
+
+    let has_common = !modules.is_empty() || !manifest.libs.is_empty();
+    manifest.map = map_of(&common).filter(|_| has_common);
+    manifest.common = has_common.then(|| common.clone());
+    let emitted = context.emit(&modules, &manifest.libs, manifest.map.as_deref())?;
+    let css = bundle_styles(&manifest.styles)?;
+
+    manifest.css = css.as_ref().map(|_| common.with_extension("css"));
+    manifest.modules = loaded_modules(&cm);
+    context.save_name_cache(root)?;
+    Ok(BundleOutput {
+        code: emitted.code,
+        map: emitted.map,
+        css,
+        license: emitted.license,
+        manifest,
+        chunks: outputs,
+    })
+}
+
+/// What the bundles of a build share
+struct Context<'a> {
+    config: &'a SwcrcConfig,
+    cm: Arc<SourceMap>,
+    globals: &'a Globals,
+    resolver: Arc<CachingResolver<ProjectResolver>>,
+    hook: &'a dyn swc_bundler::Hook,
+    comments: SwcComments,
+    /// Legal comments of the loaded modules, by source position
+    legal_comments: SwcComments,
+    loader_maps: LoaderMaps,
+    packages: Arc<Packages>,
+    plugins: Vec<Arc<dyn Plugin>>,
+    paths: SourcePaths,
+    /// `SwcrcConfig::banner` and `footer`, with their variables replaced
+    banner: Option<String>,
+    footer: Option<String>,
+    /// Names of the previous release build, when there's a name cache
+    name_cache: Option<NameCache>,
+    /// Names of this build, to save to the name cache
+    next_names: Mutex<NameCache>,
+}
+
+/// An emitted output file, with its source map and license file
+struct Emitted {
+    code: Vec<u8>,
+    map: Option<Vec<u8>>,
+    license: Option<Vec<u8>>,
+}
+
+impl Context<'_> {
+    /// Bundle entries, then run plugins and the minifier on the result. Bundles are in the order of the entries
+    fn bundle<L: Load, R: Resolve>(
+        &self,
+        loader: L,
+        resolver: R,
+        entries: Vec<(String, FileName)>,
+        external_modules: Vec<JsWord>,
+    ) -> Result<Vec<Bundle>> {
+        let minify = self.optimize();
+
+        let mut bundler = Bundler::new(
+            self.globals,
+            self.cm.clone(),
+            loader,
+            resolver,
+            swc_bundler::Config {
+                require: true,
+                disable_inliner: !self.config.inline,
+                external_modules,
+                disable_fixer: minify,
+                disable_hygiene: minify,
+                disable_dce: false,
+                module: self.config.module_type.into(),
+            },
+            Box::new(HookRef(self.hook)),
+        );
+
+        // `swc_bundler` takes entries as a map and loads them in its order, which changes from one
+        // run to the next, and so would the names it gives. Entries are bundled on their own anyway,
+        // so they're fed one at a time, which also keeps the bundles in the order of the entries.
+        // The hmr prelude is the first entry, as it has to run before anything else
+        let mut modules = vec![];
+        for (name, file) in entries {
+            modules.extend(
+                bundler
+                    .bundle(HashMap::from([(name, file)]))
+                    .map_err(Into::<Box<dyn Error>>::into)?,
+            );
+        }
+
+        if !self.plugins.is_empty() {
+            modules = modules
+                .into_iter()
+                .map(|mut b| {
+                    for plugin in &self.plugins {
+                        b.module = plugin.after_bundle(b.module)?;
+                    }
+
+                    Ok(b)
                 })
-            })
-            .collect();
+                .collect::<anyhow::Result<_>>()
+                .map_err(Into::<Box<dyn Error>>::into)?;
+        }
+
+        if minify {
+            modules = modules
+                .into_iter()
+                .map(|mut b| {
+                    let cm = self.cm.clone();
+                    let comments = &self.comments;
+
+                    GLOBALS.set(self.globals, || {
+                        // names are mangled with the cache afterwards, and properties once the
+                        // whole build is bundled
+                        let mut options = Cow::Borrowed(&self.config.minify);
+                        if self.props_options().is_some() {
+                            if let Some(mangle) = &mut options.to_mut().mangle {
+                                mangle.props = None;
+                            }
+                        }
+                        let cached_mangle = match &self.name_cache {
+                            Some(cache) if options.mangle.is_some() => {
+                                Some((cache, options.to_mut().mangle.take().unwrap()))
+                            }
+                            _ => None,
+                        };
+
+                        b.module = swc_ecma_minifier::optimize(
+                            b.module.into(),
+                            cm,
+                            Some(comments),
+                            None,
+                            &options,
+                            &ExtraOptions {
+                                unresolved_mark: Mark::new(),
+                                top_level_mark: Mark::new(),
+                            },
+                        )
+                        .expect_module();
+
+                        if let Some((cache, mangle)) = cached_mangle {
+                            let mut next = self.next_names.lock().unwrap();
+                            b.module =
+                                cache.mangle(b.module, &mangle, &self.cm, &self.paths, &mut next);
+                        }
+
+                        b.module.visit_mut_with(&mut fixer(None));
+                        b
+                    })
+                })
+                .collect();
+        }
+
+        Ok(modules)
     }
 
-    // process final bundled js
+    /// `mangle.properties` of release builds
+    fn props_options(&self) -> Option<&ManglePropertiesOptions> {
+        let mangle = self.config.minify.mangle.as_ref()?;
+        mangle.props.as_ref().filter(|_| self.optimize())
+    }
 
-    let mut output = Vec::new();
+    /// Rename the properties of all the bundles of a build at once, as they can use each other's objects
+    fn mangle_props(&self, modules: Vec<&mut Bundle>) -> Result<()> {
+        let Some(options) = self.props_options() else {
+            return Ok(());
+        };
 
-    let mut external_libs_peek = external_libs.iter().peekable();
+        let mut modules = modules
+            .into_iter()
+            .map(|b| &mut b.module)
+            .collect::<Vec<_>>();
+        let cached = self.name_cache.as_ref().map(|c| &c.props);
+        let props = mangle_props(&mut modules, options, cached.unwrap_or(&Default::default()))?;
+        self.next_names.lock().unwrap().props = props;
 
-    while let Some(lib) = external_libs_peek.next() {
-        let mut file = std::fs::File::open(lib).unwrap();
-        file.read_to_end(&mut output).unwrap();
+        Ok(())
+    }
 
-        output.trim_end();
+    /// Write the names of this build to the name cache, and the renamed properties to the
+    /// mangle report, if they changed
+    fn save_name_cache(&self, root: &Path) -> Result<()> {
+        let next = self.next_names.lock().unwrap();
 
-        if external_libs_peek.peek().is_some() {
-            output.extend_from_slice(&[b'\n', b'\n']);
-        } else {
-            output.push(b'\n');
-            // double newlines for modules
-            if !modules.is_empty() {
-                output.push(b'\n');
+        if let (Some(cache), Some(file)) = (&self.name_cache, &self.config.name_cache) {
+            if *next != *cache {
+                next.save(&root.join(file))?;
             }
         }
+
+        if let (Some(file), Some(_)) = (&self.config.mangle_report, self.props_options()) {
+            let file = root.join(file);
+            let report = serde_json::to_string_pretty(&next.props)
+                .map_err(|e| ApplicationError::GeneralError(e.into()))?
+                + "\n";
+            if fs::read_to_string(&file).ok().as_ref() != Some(&report) {
+                fs::write(file, report)?;
+            }
+        }
+
+        Ok(())
     }
 
-    let mut modules_peek = modules.iter().peekable();
-    while let Some(bundled) = modules_peek.next() {
+    /// Whether to compress and mangle, or only print without whitespace
+    fn optimize(&self) -> bool {
+        self.config.config.minify && self.config.minify_level == MinifyLevel::Full
+    }
+
+    /// A lib file, minified when the bundle is. Libs are scripts, so their top level names are
+    /// globals other scripts use, and are kept. Files which don't parse are kept as they are.
+    /// Legal comments of minified libs are added to `notices`, unless they stay inline
+    fn lib_code(&self, lib: &Path, notices: &mut Notices) -> Result<Vec<u8>> {
+        let code = fs::read_to_string(lib)?;
+        if !self.config.config.minify {
+            return Ok(code.into_bytes());
+        }
+
+        // a separate source map, so libs don't count as loaded modules
+        let cm = Arc::<SourceMap>::default();
+        let fm = cm.new_source_file(FileName::Real(lib.to_owned()), code.clone());
+        let comments = SwcComments::default();
+        let Ok(script) = parse_file_as_script(
+            &fm,
+            Syntax::Es(Default::default()),
+            EsVersion::latest(),
+            Some(&comments),
+            &mut vec![],
+        ) else {
+            return Ok(code.into_bytes());
+        };
+
+        let legal_comments = SwcComments::default();
+        for (store, legal_store) in [
+            (&comments.leading, &legal_comments.leading),
+            (&comments.trailing, &legal_comments.trailing),
+        ] {
+            for entry in store.iter() {
+                let legal = entry.value().iter().filter(|c| is_legal(c)).cloned();
+                let legal = legal.collect::<Vec<_>>();
+                if !legal.is_empty() {
+                    notices.add_comments(&legal);
+                    legal_store.insert(*entry.key(), legal);
+                }
+            }
+        }
+        let inline = self.config.legal_comments == LegalComments::Inline;
+
+        let mut options = self.config.minify.clone();
+        if let Some(compress) = &mut options.compress {
+            compress.top_level = None;
+        }
+        if let Some(mangle) = &mut options.mangle {
+            mangle.top_level = Some(false);
+            // libs are used by other scripts as they are
+            mangle.props = None;
+        }
+
+        let program = GLOBALS.set(self.globals, || {
+            if !self.optimize() {
+                return Program::Script(script);
+            }
+
+            let unresolved_mark = Mark::new();
+            let top_level_mark = Mark::new();
+
+            let mut program = Program::Script(script);
+            program.visit_mut_with(&mut resolver(unresolved_mark, top_level_mark, false));
+            let mut program = swc_ecma_minifier::optimize(
+                program,
+                cm.clone(),
+                None,
+                None,
+                &options,
+                &ExtraOptions {
+                    unresolved_mark,
+                    top_level_mark,
+                },
+            );
+            program.visit_mut_with(&mut fixer(None));
+            program
+        });
+
+        let mut output = Vec::new();
         {
             let wr = JsWriter::new(cm.clone(), "\n", &mut output, None);
             let mut emitter = Emitter {
-                cfg: swc_config.config,
-                cm: cm.clone(),
-                comments: None,
-                wr: if minify {
-                    Box::new(omit_trailing_semi(wr)) as Box<dyn WriteJs>
-                } else {
-                    Box::new(wr) as Box<dyn WriteJs>
-                },
+                cfg: self.config.config,
+                cm,
+                comments: inline.then_some(&legal_comments as _),
+                wr: Box::new(omit_trailing_semi(wr)),
             };
+            emitter.emit_program(&program)?;
+        }
+
+        Ok(output)
+    }
+
+    /// Emit lib files and bundled modules as one file, and its source map when `map_file` is set.
+    /// Legal comments go where `SwcrcConfig::legal_comments` says
+    fn emit(
+        &self,
+        modules: &[Bundle],
+        libs: &[PathBuf],
+        map_file: Option<&Path>,
+    ) -> Result<Emitted> {
+        let minify = self.config.config.minify;
+        let cm = &self.cm;
+        let legal = self.config.legal_comments;
+
+        // the emitter takes the comments it writes, and a module can go into several outputs
+        let inline_comments = SwcComments::default();
+        if legal == LegalComments::Inline {
+            for entry in self.legal_comments.leading.iter() {
+                inline_comments
+                    .leading
+                    .insert(*entry.key(), entry.value().clone());
+            }
+            for entry in self.legal_comments.trailing.iter() {
+                inline_comments
+                    .trailing
+                    .insert(*entry.key(), entry.value().clone());
+            }
+        }
+
+        let mut notices = Notices::default();
+
+        let mut output = Vec::new();
 
-            emitter.emit_module(&bundled.module).unwrap();
+        // the source map goes by the lines before each module, so it stays in line with the banner
+        if let Some(banner) = &self.banner {
+            output.extend_from_slice(banner.trim_end().as_bytes());
+            output.push(b'\n');
         }
 
-        output.trim_end();
+        let mut external_libs_peek = libs.iter().peekable();
+
+        while let Some(lib) = external_libs_peek.next() {
+            output.extend(self.lib_code(lib, &mut notices)?);
+
+            output.trim_end();
+
+            if external_libs_peek.peek().is_some() {
+                output.extend_from_slice(&[b'\n', b'\n']);
+            } else {
+                output.push(b'\n');
+                // double newlines for modules
+                if !modules.is_empty() {
+                    output.push(b'\n');
+                }
+            }
+        }
+
+        for bundled in modules {
+            notices.collect(&bundled.module, cm, &self.legal_comments, &self.packages);
+        }
+
+        let mut srcmap = self.config.source_maps.then(Vec::new);
+
+        let mut modules_peek = modules.iter().peekable();
+        while let Some(bundled) = modules_peek.next() {
+            // source map lines are relative to the start of the emitted module
+            let line_offset = output.iter().filter(|&&b| b == b'\n').count() as u32;
+            let mut module_srcmap = srcmap.as_ref().map(|_| Vec::new());
+
+            {
+                let wr = JsWriter::new(cm.clone(), "\n", &mut output, module_srcmap.as_mut());
+                let mut emitter = Emitter {
+                    cfg: self.config.config,
+                    cm: cm.clone(),
+                    comments: (legal == LegalComments::Inline).then_some(&inline_comments as _),
+                    wr: if minify {
+                        Box::new(omit_trailing_semi(wr)) as Box<dyn WriteJs>
+                    } else {
+                        Box::new(wr) as Box<dyn WriteJs>
+                    },
+                };
+
+                emitter.emit_module(&bundled.module).unwrap();
+            }
 
-        if modules_peek.peek().is_some() {
-            output.extend_from_slice(&[b'\n', b'\n']);
-        } else {
+            if let (Some(srcmap), Some(module_srcmap)) = (&mut srcmap, module_srcmap) {
+                srcmap.extend(module_srcmap.into_iter().map(|(pos, mut line_col)| {
+                    line_col.line += line_offset;
+                    (pos, line_col)
+                }));
+            }
+
+            output.trim_end();
+
+            if modules_peek.peek().is_some() {
+                output.extend_from_slice(&[b'\n', b'\n']);
+            } else {
+                output.push(b'\n');
+            }
+        }
+
+        if let Some(footer) = &self.footer {
+            output.extend_from_slice(footer.trim_end().as_bytes());
             output.push(b'\n');
         }
+
+        // inline comments of code the minifier moved or dropped still go at the end
+        let dropped = inline_comments
+            .leading
+            .iter()
+            .chain(inline_comments.trailing.iter())
+            .flat_map(|entry| entry.value().iter().map(to_source).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for comment in notices.comments() {
+            if legal == LegalComments::Eof || dropped.contains(comment) {
+                output.extend_from_slice(format!("{comment}\n").as_bytes());
+            }
+        }
+
+        let map = match (srcmap, map_file) {
+            (Some(srcmap), Some(map_file)) => {
+                let mut map = Vec::new();
+                let srcmap = cm.build_source_map_with_config(&srcmap, None, &self.paths);
+                chain_loader_maps(srcmap, &self.loader_maps, &self.paths)
+                    .map_err(Into::<Box<dyn Error>>::into)?
+                    .to_writer(&mut map)
+                    .map_err(Into::<Box<dyn Error>>::into)?;
+
+                let map_file = map_file.file_name().ok_or(ApplicationError::PathError)?;
+                output.extend_from_slice(
+                    format!("//# sourceMappingURL={}\n", map_file.to_string_lossy()).as_bytes(),
+                );
+
+                Some(map)
+            }
+
+            _ => None,
+        };
+
+        let license = match legal {
+            LegalComments::External => notices.license_file(),
+            _ if self.config.license_file => notices.license_file(),
+            _ => None,
+        };
+
+        Ok(Emitted {
+            code: output,
+            map,
+            license,
+        })
+    }
+}
+
+/// Lib stylesheets, one after the other
+fn bundle_styles(styles: &[PathBuf]) -> Result<Option<Vec<u8>>> {
+    if styles.is_empty() {
+        return Ok(None);
     }
 
-    let mut asset_manager = asset_manager.lock().unwrap();
-    let asset = AssetType::Memory(output);
-    asset_manager.add(asset, output_bundle_js);
+    let mut output = Vec::new();
+    for style in styles {
+        output.extend(fs::read(style)?);
+        output.trim_end();
+        output.extend_from_slice(b"\n\n");
+    }
+    output.pop();
+
+    Ok(Some(output))
+}
+
+/// Lends the build's hook to each of its bundlers
+struct HookRef<'a>(&'a dyn swc_bundler::Hook);
+
+impl swc_bundler::Hook for HookRef<'_> {
+    fn get_import_meta_props(
+        &self,
+        span: Span,
+        module_record: &ModuleRecord,
+    ) -> anyhow::Result<Vec<KeyValueProp>> {
+        self.0.get_import_meta_props(span, module_record)
+    }
+}
+
+/// Source files loaded into the bundles, once each. Modules entries couldn't share are loaded for every entry
+fn loaded_modules(cm: &SourceMap) -> Vec<PathBuf> {
+    let mut modules = Vec::<PathBuf>::new();
+    for file in cm.files().iter() {
+        if let FileName::Real(path) = &file.name {
+            if !modules.contains(path) {
+                modules.push(path.clone());
+            }
+        }
+    }
 
-    Ok(())
+    modules
 }
@@ -0,0 +1,722 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Error};
+use rustc_hash::{FxHashMap, FxHashSet};
+use swc_atoms::JsWord;
+use swc_bundler::{Load, ModuleData};
+use swc_common::{collections::AHashMap, FileName, DUMMY_SP};
+use swc_ecma_ast::{
+    CallExpr, Callee, EsVersion, ExportNamedSpecifier, ExportSpecifier, Expr, Ident, ImportDecl,
+    ImportNamedSpecifier, ImportSpecifier, Lit, Module, ModuleDecl, ModuleExportName, ModuleItem,
+    NamedExport, Str,
+};
+use swc_ecma_loader::resolve::Resolve;
+use swc_ecma_parser::parse_file_as_module;
+use swc_ecma_visit::{Visit, VisitMutWith, VisitWith};
+
+use super::{external_globals::ExternalGlobals, loading::Loader, ModuleType};
+
+/// Name of the chunk with the modules entries share
+pub const COMMON_CHUNK: &str = "common";
+/// The entry module of the common chunk, which exports what entries import from shared modules
+const COMMON_MODULE: &str = "<common chunk>";
+/// Prefix of the global the common chunk of iife bundles is assigned to. Every common chunk of a page
+/// gets its own, named after its output file
+const COMMON_GLOBAL: &str = "__trunk_common";
+
+/// Entry names and their files, in the order they're bundled
+pub type Entries = Vec<(String, FileName)>;
+
+/// How a build splits its entries into several files
+pub struct Split {
+    /// Name, output file and entries of each file
+    pub chunks: Vec<(String, PathBuf, Entries)>,
+    /// Output file of the common chunk, with the modules more than one of them uses
+    pub common: PathBuf,
+}
+
+/// What a module uses of a module it imports
+enum Uses {
+    /// Names from `import` and `export ... from`. `*` is the namespace
+    Names(Vec<JsWord>),
+    /// `export * from`, `require()` and `import()`, which can't be pointed at the common chunk
+    Everything,
+}
+
+struct Import {
+    src: JsWord,
+    target: FileName,
+    uses: Uses,
+}
+
+/// Splits entries into several bundles, and a common chunk with the modules more than one
+/// of the bundles reaches. `swc_bundler` can't split bundles itself, so imports of shared modules are
+/// pointed at the common chunk, which exports everything they use as `__m{module}_{name}`.
+/// Shared modules which are star re-exported, `require()`d or dynamically imported by a bundle are
+/// left in each bundle
+pub struct Chunks {
+    module_type: ModuleType,
+    /// What the common chunk of iife bundles is assigned to
+    global: String,
+    /// Modules loaded while walking the module graph, until the bundler takes them
+    loaded: Mutex<FxHashMap<FileName, ModuleData>>,
+    imports: FxHashMap<FileName, Vec<Import>>,
+    shared: FxHashSet<FileName>,
+    /// Shared modules imported by entries, and the names they import, in the order they were found
+    boundaries: Vec<(FileName, Vec<JsWord>)>,
+}
+
+impl Chunks {
+    pub fn new(
+        loader: &Loader,
+        resolver: &dyn Resolve,
+        chunks: &[(String, PathBuf, Entries)],
+        common: &Path,
+        external_modules: &[JsWord],
+        module_type: ModuleType,
+    ) -> Result<Self, Error> {
+        let mut loaded = FxHashMap::default();
+        let mut imports = FxHashMap::default();
+        let mut order = vec![];
+
+        let entries = chunks
+            .iter()
+            .map(|(.., entries)| entries.iter().map(|(_, file)| file).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let mut stack = entries
+            .iter()
+            .flatten()
+            .rev()
+            .map(|f| (*f).clone())
+            .collect::<Vec<_>>();
+        while let Some(file) = stack.pop() {
+            if imports.contains_key(&file) {
+                continue;
+            }
+
+            let data = loader.load(&file)?;
+            let mut module_imports = vec![];
+            for (src, uses) in find_imports(&data.module) {
+                if external_modules.contains(&src) {
+                    continue;
+                }
+                let target = resolver.resolve(&file, &src)?;
+                module_imports.push(Import { src, target, uses });
+            }
+
+            stack.extend(module_imports.iter().rev().map(|i| i.target.clone()));
+            order.push(file.clone());
+            imports.insert(file.clone(), module_imports);
+            loaded.insert(file, data);
+        }
+
+        let shared = shared_modules(&imports, &entries);
+
+        let mut boundaries = Vec::<(FileName, Vec<JsWord>)>::new();
+        for file in order.iter().filter(|f| !shared.contains(*f)) {
+            for import in imports[file].iter().filter(|i| shared.contains(&i.target)) {
+                let index = match boundaries.iter().position(|(f, _)| *f == import.target) {
+                    Some(index) => index,
+                    None => {
+                        boundaries.push((import.target.clone(), vec![]));
+                        boundaries.len() - 1
+                    }
+                };
+
+                let names = &mut boundaries[index].1;
+                if let Uses::Names(used) = &import.uses {
+                    for name in used {
+                        if !names.contains(name) {
+                            names.push(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            module_type,
+            global: common_global(common),
+            loaded: Mutex::new(loaded),
+            imports,
+            shared,
+            boundaries,
+        })
+    }
+
+    /// Whether any module is shared between entries
+    pub fn is_empty(&self) -> bool {
+        self.shared.is_empty()
+    }
+
+    /// The module to bundle as the common chunk
+    pub fn common_module(&self) -> FileName {
+        FileName::Custom(COMMON_MODULE.to_owned())
+    }
+
+    /// What entries import the common chunk as, given its path relative to them
+    pub fn specifier(&self, common: &str) -> String {
+        match self.module_type {
+            ModuleType::Es => common.to_owned(),
+            ModuleType::Iife => COMMON_MODULE.to_owned(),
+        }
+    }
+
+    fn export_name(&self, index: usize, name: &JsWord) -> JsWord {
+        let names = &self.boundaries[index].1;
+        let position = names.iter().position(|n| n == name).unwrap_or_default();
+        format!("__m{index}_{position}").into()
+    }
+
+    /// Source of the common chunk's entry module
+    fn common_source(&self) -> String {
+        let mut source = String::new();
+        let mut exports = vec![];
+
+        for (index, (file, names)) in self.boundaries.iter().enumerate() {
+            let src = serde_json::Value::String(file.to_string());
+            if names.is_empty() {
+                let _ = writeln!(source, "import {src};");
+            }
+
+            for name in names {
+                let local = self.export_name(index, name);
+                let _ = match &**name {
+                    "*" => writeln!(source, "import * as {local} from {src};"),
+                    name => writeln!(source, "import {{ {name} as {local} }} from {src};"),
+                };
+                exports.push(local.to_string());
+            }
+        }
+
+        // getters keep the bindings live, like imports
+        match self.module_type {
+            ModuleType::Es => {
+                let _ = writeln!(source, "export {{ {} }};", exports.join(", "));
+            }
+            ModuleType::Iife => {
+                let getters = exports
+                    .iter()
+                    .map(|e| format!("get {e}() {{ return {e}; }}"))
+                    .collect::<Vec<_>>();
+                let _ = writeln!(
+                    source,
+                    "window.{} = {{ {} }};",
+                    self.global,
+                    getters.join(", ")
+                );
+            }
+        }
+
+        source
+    }
+
+    /// Point the imports of shared modules in an entry's module at the common chunk
+    fn rewrite(&self, file: &FileName, module: &mut Module, specifier: &str) -> Result<(), Error> {
+        let Some(imports) = self.imports.get(file).filter(|_| !self.shared.contains(file)) else {
+            return Ok(());
+        };
+
+        let shared_index = |src: &JsWord| {
+            let import = imports.iter().find(|i| i.src == *src)?;
+            self.boundaries
+                .iter()
+                .position(|(f, _)| *f == import.target)
+        };
+
+        let mut locals = 0;
+        let mut body = Vec::with_capacity(module.body.len());
+        for item in module.body.drain(..) {
+            match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(import)) if !import.type_only => {
+                    let Some(index) = shared_index(&import.src.value) else {
+                        body.push(ModuleItem::ModuleDecl(ModuleDecl::Import(import)));
+                        continue;
+                    };
+
+                    let specifiers = import
+                        .specifiers
+                        .iter()
+                        .map(|s| {
+                            let (local, name) = match s {
+                                ImportSpecifier::Named(s) => {
+                                    let name = match &s.imported {
+                                        Some(name) => export_name(name),
+                                        None => s.local.sym.clone(),
+                                    };
+                                    (s.local.clone(), name)
+                                }
+                                ImportSpecifier::Default(s) => (s.local.clone(), "default".into()),
+                                ImportSpecifier::Namespace(s) => (s.local.clone(), "*".into()),
+                            };
+                            named_import(local, self.export_name(index, &name))
+                        })
+                        .collect();
+
+                    body.push(import_from(specifiers, specifier));
+                }
+
+                // re-exports become an import, and an export of what was imported
+                ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export))
+                    if export.src.is_some() && !export.type_only =>
+                {
+                    let Some(index) = export.src.as_ref().and_then(|s| shared_index(&s.value))
+                    else {
+                        body.push(ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)));
+                        continue;
+                    };
+
+                    let mut specifiers = vec![];
+                    let mut exports = vec![];
+                    for s in &export.specifiers {
+                        let (name, exported) = match s {
+                            ExportSpecifier::Named(s) => (
+                                export_name(&s.orig),
+                                s.exported.clone().unwrap_or(s.orig.clone()),
+                            ),
+                            ExportSpecifier::Namespace(s) => ("*".into(), s.name.clone()),
+                            // `export v from`, which is filtered out when walking the graph
+                            ExportSpecifier::Default(_) => continue,
+                        };
+
+                        let local = Ident::new(format!("__chunk_{locals}").into(), DUMMY_SP);
+                        locals += 1;
+
+                        specifiers
+                            .push(named_import(local.clone(), self.export_name(index, &name)));
+                        exports.push(ExportSpecifier::Named(ExportNamedSpecifier {
+                            span: DUMMY_SP,
+                            orig: ModuleExportName::Ident(local),
+                            exported: Some(exported),
+                            is_type_only: false,
+                        }));
+                    }
+
+                    body.push(import_from(specifiers, specifier));
+                    body.push(ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(
+                        NamedExport {
+                            span: DUMMY_SP,
+                            specifiers: exports,
+                            src: None,
+                            type_only: false,
+                            asserts: None,
+                        },
+                    )));
+                }
+
+                item => body.push(item),
+            }
+        }
+        module.body = body;
+
+        // iife bundles can't import, the common chunk is a global
+        if self.module_type == ModuleType::Iife {
+            let globals = AHashMap::from_iter([(specifier.to_owned(), self.global.clone())]);
+            let mut external_globals = ExternalGlobals::new(&globals);
+            module.visit_mut_with(&mut external_globals);
+            if let Some(error) = external_globals.error {
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads modules for one of the bundles of `Chunks`
+pub struct ChunkLoader<'a> {
+    pub loader: &'a Loader,
+    pub chunks: &'a Chunks,
+    /// What the common chunk is imported as. `None` when bundling the common chunk itself
+    pub specifier: Option<&'a str>,
+}
+
+impl Load for ChunkLoader<'_> {
+    fn load(&self, file: &FileName) -> Result<ModuleData, Error> {
+        if matches!(file, FileName::Custom(name) if name == COMMON_MODULE) {
+            let fm = self
+                .loader
+                .cm
+                .new_source_file(file.clone(), self.chunks.common_source());
+            let module = parse_file_as_module(
+                &fm,
+                Default::default(),
+                EsVersion::latest(),
+                None,
+                &mut vec![],
+            )
+            .map_err(|_| anyhow!("Failed to parse the common chunk"))?;
+
+            return Ok(ModuleData {
+                fm,
+                module,
+                helpers: Default::default(),
+            });
+        }
+
+        let loaded = self.chunks.loaded.lock().unwrap().remove(file);
+        let mut data = match loaded {
+            Some(data) => data,
+            // modules of several entries which couldn't be shared are loaded for each of them
+            None => self.loader.load(file)?,
+        };
+
+        if let Some(specifier) = self.specifier {
+            self.chunks.rewrite(file, &mut data.module, specifier)?;
+        }
+
+        Ok(data)
+    }
+}
+
+/// Resolves the imports of the common chunk's entry module, which are all absolute paths
+pub struct ChunkResolver<R> {
+    pub inner: R,
+}
+
+impl<R: Resolve> Resolve for ChunkResolver<R> {
+    fn resolve(&self, base: &FileName, module_specifier: &str) -> Result<FileName, Error> {
+        match base {
+            FileName::Custom(name) if name == COMMON_MODULE => {
+                Ok(FileName::Real(module_specifier.into()))
+            }
+            _ => self.inner.resolve(base, module_specifier),
+        }
+    }
+}
+
+/// The global of the common chunk written to `common`, so that pages with several of them keep them apart
+fn common_global(common: &Path) -> String {
+    let name = common.with_extension("").display().to_string();
+    let name = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    format!("{COMMON_GLOBAL}_{name}")
+}
+
+/// The modules more than one chunk reaches, which can be moved into the common chunk
+fn shared_modules(
+    imports: &FxHashMap<FileName, Vec<Import>>,
+    entries: &[Vec<&FileName>],
+) -> FxHashSet<FileName> {
+    let reach = |from: &FileName| {
+        let mut seen = FxHashSet::default();
+        let mut stack = vec![from.clone()];
+        while let Some(file) = stack.pop() {
+            if let Some(file_imports) = imports.get(&file).filter(|_| seen.insert(file)) {
+                stack.extend(file_imports.iter().map(|i| i.target.clone()));
+            }
+        }
+        seen
+    };
+
+    let mut counts = FxHashMap::<FileName, usize>::default();
+    for chunk_entries in entries {
+        let reached = chunk_entries
+            .iter()
+            .flat_map(|e| reach(e))
+            .collect::<FxHashSet<_>>();
+        for file in reached {
+            *counts.entry(file).or_default() += 1;
+        }
+    }
+
+    // entries and virtual modules stay where they are
+    let mut shared = counts
+        .into_iter()
+        .filter(|(file, count)| {
+            *count > 1
+                && matches!(file, FileName::Real(_))
+                && !entries.iter().flatten().any(|e| *e == file)
+        })
+        .map(|(file, _)| file)
+        .collect::<FxHashSet<_>>();
+
+    // what can't be imported from the common chunk goes back into the entries, with all it imports.
+    // Shared modules importing what isn't shared follow it, or the common chunk would get a
+    // second instance of it
+    loop {
+        let mut demoted = imports
+            .iter()
+            .filter(|(file, _)| !shared.contains(*file))
+            .flat_map(|(_, imports)| imports)
+            .filter(|i| shared.contains(&i.target) && !is_importable(&i.uses))
+            .flat_map(|i| reach(&i.target))
+            .collect::<FxHashSet<_>>();
+        demoted.extend(
+            imports
+                .iter()
+                .filter(|(file, imports)| {
+                    shared.contains(*file) && imports.iter().any(|i| !shared.contains(&i.target))
+                })
+                .map(|(file, _)| file.clone()),
+        );
+        demoted.retain(|file| shared.contains(file));
+        if demoted.is_empty() {
+            break;
+        }
+
+        for file in &demoted {
+            shared.remove(file);
+        }
+    }
+
+    shared
+}
+
+fn is_importable(uses: &Uses) -> bool {
+    let is_ident = |name: &str| {
+        !name.starts_with(|c: char| c.is_ascii_digit())
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    };
+
+    match uses {
+        Uses::Names(names) => names.iter().all(|n| &**n == "*" || is_ident(n)),
+        Uses::Everything => false,
+    }
+}
+
+fn export_name(name: &ModuleExportName) -> JsWord {
+    match name {
+        ModuleExportName::Ident(i) => i.sym.clone(),
+        ModuleExportName::Str(s) => s.value.clone(),
+    }
+}
+
+fn named_import(local: Ident, imported: JsWord) -> ImportSpecifier {
+    ImportSpecifier::Named(ImportNamedSpecifier {
+        span: DUMMY_SP,
+        local,
+        imported: Some(ModuleExportName::Ident(Ident::new(imported, DUMMY_SP))),
+        is_type_only: false,
+    })
+}
+
+fn import_from(specifiers: Vec<ImportSpecifier>, src: &str) -> ModuleItem {
+    ModuleItem::ModuleDecl(ModuleDecl::Import(ImportDecl {
+        span: DUMMY_SP,
+        specifiers,
+        src: Box::new(Str::from(src)),
+        type_only: false,
+        asserts: None,
+    }))
+}
+
+/// The modules a module imports, and what it uses of them
+fn find_imports(module: &Module) -> Vec<(JsWord, Uses)> {
+    let mut imports = vec![];
+    for item in &module.body {
+        let ModuleItem::ModuleDecl(decl) = item else {
+            continue;
+        };
+
+        match decl {
+            ModuleDecl::Import(import) if !import.type_only => {
+                let names = import
+                    .specifiers
+                    .iter()
+                    .map(|s| match s {
+                        ImportSpecifier::Named(s) => match &s.imported {
+                            Some(name) => export_name(name),
+                            None => s.local.sym.clone(),
+                        },
+                        ImportSpecifier::Default(_) => "default".into(),
+                        ImportSpecifier::Namespace(_) => "*".into(),
+                    })
+                    .collect();
+                imports.push((import.src.value.clone(), Uses::Names(names)));
+            }
+            ModuleDecl::ExportNamed(NamedExport {
+                src: Some(src),
+                specifiers,
+                type_only: false,
+                ..
+            }) => {
+                let names = specifiers
+                    .iter()
+                    .map(|s| match s {
+                        ExportSpecifier::Named(s) => Some(export_name(&s.orig)),
+                        ExportSpecifier::Namespace(_) => Some("*".into()),
+                        ExportSpecifier::Default(_) => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+
+                let uses = names.map_or(Uses::Everything, Uses::Names);
+                imports.push((src.value.clone(), uses));
+            }
+            ModuleDecl::ExportAll(all) => {
+                imports.push((all.src.value.clone(), Uses::Everything));
+            }
+            _ => {}
+        }
+    }
+
+    let mut calls = ImportCalls::default();
+    module.visit_with(&mut calls);
+    imports.extend(calls.srcs.into_iter().map(|src| (src, Uses::Everything)));
+
+    imports
+}
+
+/// `require()` and dynamic `import()` calls with a string literal
+#[derive(Default)]
+struct ImportCalls {
+    srcs: Vec<JsWord>,
+}
+
+impl Visit for ImportCalls {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        call.visit_children_with(self);
+
+        let is_import = match &call.callee {
+            Callee::Expr(callee) => matches!(&**callee, Expr::Ident(i) if &*i.sym == "require"),
+            Callee::Import(_) => true,
+            Callee::Super(_) => false,
+        };
+        if let (true, Some(Expr::Lit(Lit::Str(src)))) =
+            (is_import, call.args.first().map(|a| &*a.expr))
+        {
+            self.srcs.push(src.value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swc_common::SourceMap;
+    use swc_ecma_parser::parse_file_as_module;
+
+    use super::*;
+
+    fn file(name: &str) -> FileName {
+        FileName::Real(format!("/project/{name}.js").into())
+    }
+
+    /// Module graph from `(file, [(target, importable)])`
+    fn graph(modules: &[(&str, &[(&str, bool)])]) -> FxHashMap<FileName, Vec<Import>> {
+        modules
+            .iter()
+            .map(|(name, targets)| {
+                let imports = targets
+                    .iter()
+                    .map(|&(target, importable)| Import {
+                        src: format!("./{target}.js").into(),
+                        target: file(target),
+                        uses: match importable {
+                            true => Uses::Names(vec!["default".into()]),
+                            false => Uses::Everything,
+                        },
+                    })
+                    .collect();
+                (file(name), imports)
+            })
+            .collect()
+    }
+
+    fn shared(imports: &FxHashMap<FileName, Vec<Import>>, entries: &[&str]) -> Vec<String> {
+        let entries = entries.iter().map(|e| file(e)).collect::<Vec<_>>();
+        let entries = entries.iter().map(|e| vec![e]).collect::<Vec<_>>();
+
+        let mut shared = shared_modules(imports, &entries)
+            .into_iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        shared.sort();
+        shared
+    }
+
+    #[test]
+    fn shares_modules_reached_by_several_chunks() {
+        let imports = graph(&[
+            ("a", &[("react", true), ("only_a", true)]),
+            ("b", &[("react", true)]),
+            ("react", &[]),
+            ("only_a", &[]),
+        ]);
+
+        assert_eq!(shared(&imports, &["a", "b"]), ["/project/react.js"]);
+    }
+
+    #[test]
+    fn demotes_what_is_not_importable_with_what_it_imports() {
+        let imports = graph(&[
+            ("a", &[("lib", false)]),
+            ("b", &[("lib", true), ("util", true)]),
+            ("lib", &[("util", true)]),
+            ("util", &[]),
+        ]);
+
+        assert!(shared(&imports, &["a", "b"]).is_empty());
+    }
+
+    #[test]
+    fn demotes_shared_importers_of_demoted_modules() {
+        // `inner` goes back into the entries, so `outer` and `top` can't stay in the common chunk
+        // without a second copy of it
+        let imports = graph(&[
+            ("a", &[("top", true), ("inner", false)]),
+            ("b", &[("top", true), ("inner", false)]),
+            ("top", &[("outer", true), ("other", true)]),
+            ("outer", &[("inner", true)]),
+            ("inner", &[]),
+            ("other", &[]),
+        ]);
+
+        assert_eq!(shared(&imports, &["a", "b"]), ["/project/other.js"]);
+    }
+
+    #[test]
+    fn names_the_common_global_after_its_output() {
+        assert_eq!(
+            common_global(Path::new("js/common.js")),
+            "__trunk_common_js_common"
+        );
+        assert_ne!(
+            common_global(Path::new("admin.js")),
+            common_global(Path::new("app.js"))
+        );
+    }
+
+    #[test]
+    fn finds_dynamic_imports_and_requires() {
+        let cm = SourceMap::default();
+        let fm = cm.new_source_file(
+            FileName::Anon,
+            r#"
+            import a from "./a";
+            export * from "./b";
+            const c = require("./c");
+            const d = () => import("./d");
+            import(dynamic);
+            "#
+            .to_owned(),
+        );
+        let module = parse_file_as_module(
+            &fm,
+            Default::default(),
+            Default::default(),
+            None,
+            &mut vec![],
+        )
+        .unwrap();
+
+        let imports = find_imports(&module)
+            .into_iter()
+            .map(|(src, uses)| (src.to_string(), is_importable(&uses)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            imports,
+            [
+                ("./a".to_owned(), true),
+                ("./b".to_owned(), false),
+                ("./c".to_owned(), false),
+                ("./d".to_owned(), false),
+            ]
+        );
+    }
+}
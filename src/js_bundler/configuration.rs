@@ -1,11 +1,28 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use swc::config::PluginConfig;
 use swc_bundler::ModuleType as SwcModuleType;
-use swc_common::collections::AHashMap;
+use swc_cached::regex::CachedRegex;
+use swc_common::{
+    collections::AHashMap,
+    errors::{ColorConfig, Handler},
+    sync::Lrc,
+    SourceMap,
+};
 use swc_ecma_ast::EsVersion;
 use swc_ecma_codegen::Config;
-use swc_ecma_minifier::option::{CompressOptions, MangleOptions, MinifyOptions, TopLevelOptions};
+use swc_ecma_minifier::option::{
+    terser::TerserCompressorOptions, CompressOptions, MangleOptions, ManglePropertiesOptions,
+    MinifyOptions, TopLevelOptions,
+};
+use swc_ecma_transforms_react::Options as JsxOptions;
+
+use crate::errors::{ApplicationError, Result};
 
 #[derive(Deserialize, Debug, Default, Clone)]
 pub struct SwcrcConfig {
@@ -15,19 +32,326 @@ pub struct SwcrcConfig {
     pub output: Option<PathBuf>,
     #[serde(default = "always_true")]
     pub inline: bool,
+    #[serde(default)]
     pub modules: Vec<String>,
     #[serde(rename = "externalModules")]
     pub external_modules: Option<Vec<String>>,
-    #[serde(default)]
+    /// Minifier options of release builds. In a config file, these are in the terser format of
+    /// `.swcrc`'s `jsc.minify` (`compress`, `mangle`, `keep_classnames`, `keep_fnames`), and
+    /// `compress` and `mangle` default to the release ones
+    #[serde(default = "release_minify_options")]
+    #[serde(deserialize_with = "terser_minify_options")]
     pub minify: MinifyOptions,
+    /// How much release builds are minified
+    #[serde(default)]
+    #[serde(rename = "minifyLevel")]
+    pub minify_level: MinifyLevel,
+    /// Json file, relative to the project root, which keeps the mangled names of release builds,
+    /// so names which didn't change keep the same mangled name. It's created if it doesn't exist
+    #[serde(rename = "nameCache")]
+    pub name_cache: Option<PathBuf>,
+    /// Json file, relative to the project root, listing the properties release builds renamed
+    /// with `mangle.properties`, and their new names
+    #[serde(rename = "mangleReport")]
+    pub mangle_report: Option<PathBuf>,
     #[serde(default)]
     pub config: Config,
+    #[serde(default)]
+    #[serde(rename = "sourceMaps")]
+    pub source_maps: bool,
     pub env: Option<AHashMap<String, String>>,
     pub vars: Option<AHashMap<String, String>>,
     pub typeofs: Option<AHashMap<String, String>>,
+    /// Same as `jsc.transform.react` in `.swcrc`. Falls back to `tsconfig.json` for unset options
+    pub jsx: Option<JsxOptions>,
+    /// Swc wasm plugins, in the same format as `jsc.experimental.plugins` in `.swcrc`
+    pub plugins: Option<Vec<PluginConfig>>,
+    /// Where compiled wasm plugins are cached, same as `jsc.experimental.cacheRoot` in `.swcrc`
+    #[serde(rename = "cacheRoot")]
+    pub cache_root: Option<String>,
+    /// Hot module reloading with react-refresh. Only used in debug builds
+    pub hmr: Option<HmrOptions>,
+    /// File extensions (without the `.`) compiled to js by an external command
+    pub loaders: Option<AHashMap<String, ExternalLoader>>,
+    /// Import specifier prefixes to replace, e.g. `"@app": "./src"`. Targets starting with `.`
+    /// are relative to the project root, anything else is a package name.
+    /// `tsconfig.json` `paths` and `baseUrl` are applied as well
+    pub alias: Option<AHashMap<String, String>>,
+    /// Extra `package.json` `exports`/`imports` conditions. `browser`, `module`, `import`, `default`,
+    /// and `development` or `production` are always matched
+    pub conditions: Option<Vec<String>>,
+    /// Shim Node built-ins (`buffer`, `events`, `path`, ...) with browser polyfills, and define
+    /// `process`, `global` and `Buffer` for modules which use them
+    #[serde(default)]
+    #[serde(rename = "nodeShims")]
+    pub node_shims: bool,
+    /// Globals which external modules are loaded into, e.g. `"react": "React"`.
+    /// Imports of them become reads from `window.React`, for iife bundles
+    #[serde(rename = "externalGlobals")]
+    pub external_globals: Option<AHashMap<String, String>>,
+    /// Keep the symlinked paths of packages, like node's `--preserve-symlinks`, instead of
+    /// resolving them to their real path. Files reached through several symlinks are still only bundled once
+    #[serde(default)]
+    #[serde(rename = "preserveSymlinks")]
+    pub preserve_symlinks: bool,
+    /// Several outputs bundled from one module graph, instead of `modules`. Modules more than
+    /// one of them uses go into a common chunk at `output`, which has to load first
+    pub outputs: Option<Vec<OutputConfig>>,
+    /// Folders and files of `lib` to add, ordered as in `lib/manifest.json`. Every lib when `None`
+    pub libs: Option<Vec<String>>,
+    /// What to do with `/*!`, `@license` and `@preserve` comments
+    #[serde(default)]
+    #[serde(rename = "legalComments")]
+    pub legal_comments: LegalComments,
+    /// Write the legal comments and the npm packages (with their `license`) which went into
+    /// an output to `<output>.LICENSE.txt`
+    #[serde(default)]
+    #[serde(rename = "licenseFile")]
+    pub license_file: bool,
+    /// Written as is at the top of every output file, e.g. a `/*! ... */` copyright comment.
+    /// `[package]`, `[version]` (from `package.json`), `[commit]`, `[timestamp]` and `[profile]`
+    /// are replaced with the build's
+    pub banner: Option<String>,
+    /// Written as is at the end of every output file, with the same variables as `banner`
+    pub footer: Option<String>,
+    /// The Rust crate of the Trunk project, for the `__APP_VERSION__` and `__CARGO_FEATURES__` globals
+    #[serde(default)]
+    pub cargo: CargoOptions,
+}
+
+/// Which crate the app is built from, and with which features, like the `data-cargo-*` attributes
+/// of Trunk's `rust` link
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct CargoOptions {
+    /// `Cargo.toml`, or the directory it's in, relative to the project root. The closest one above
+    /// the project root when unset
+    pub manifest: Option<PathBuf>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    #[serde(rename = "allFeatures")]
+    pub all_features: bool,
+    #[serde(default)]
+    #[serde(rename = "noDefaultFeatures")]
+    pub no_default_features: bool,
+}
+
+/// Where legal comments end up, like esbuild's `--legal-comments`
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LegalComments {
+    /// Where they are in the source
+    Inline,
+    /// Collected at the end of the output
+    #[default]
+    Eof,
+    /// Only in the `.LICENSE.txt` file
+    External,
+    /// Dropped
+    None,
+}
+
+impl SwcrcConfig {
+    /// Read a json config file. Unset `moduleType` and `config` (codegen options) are those of `generate_config`
+    pub fn from_file(path: &Path, debug: bool) -> Result<Self> {
+        let error = |e: &dyn std::fmt::Display| {
+            ApplicationError::GeneralError(format!("{}: {e}", path.display()).into())
+        };
+        let json = fs::read_to_string(path)?;
+        let value = serde_json::from_str::<serde_json::Value>(&json).map_err(|e| error(&e))?;
+        let has = |key: &str| value.get(key).is_some();
+
+        let defaults = generate_config(debug);
+        let mut config = Self::deserialize(&value).map_err(|e| error(&e))?;
+        if !has("moduleType") {
+            config.module_type = defaults.module_type;
+        }
+        if !has("config") {
+            config.config = defaults.config;
+        }
+
+        Ok(config)
+    }
+
+    /// Remove `console.*` calls in release builds. Fails if the config turns `minify.compress` off
+    pub fn drop_console(&mut self) -> Result<()> {
+        if let Some(compress) = self.minify_section(|m| &mut m.compress, "drop console calls")? {
+            compress.drop_console = true;
+        }
+
+        Ok(())
+    }
+
+    /// Keep class and function names in release builds, for code which reads `.name`. With
+    /// `minify.compress` or `minify.mangle` turned off, that one doesn't rename anything anyway
+    pub fn keep_names(&mut self) {
+        if let Some(compress) = &mut self.minify.compress {
+            compress.keep_classnames = true;
+            compress.keep_fnames = true;
+        }
+        if let Some(mangle) = &mut self.minify.mangle {
+            mangle.keep_class_names = true;
+            mangle.keep_fn_names = true;
+        }
+    }
+
+    /// Rename the properties matching `regex` in release builds, except `reserved` ones. Fails if
+    /// the config turns `minify.mangle` off
+    pub fn mangle_props(&mut self, regex: &str, reserved: Vec<String>) -> Result<()> {
+        let regex = CachedRegex::new(regex)
+            .map_err(|e| ApplicationError::GeneralError(e.to_string().into()))?;
+
+        if let Some(mangle) = self.minify_section(|m| &mut m.mangle, "mangle properties")? {
+            mangle.props = Some(ManglePropertiesOptions {
+                reserved: reserved.into_iter().map(Into::into).collect(),
+                undeclared: None,
+                regex: Some(regex),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// A section of the release minifier options, which has to be there to `what`. Debug builds,
+    /// and release ones which aren't fully minified, don't use it
+    fn minify_section<T>(
+        &mut self,
+        section: impl FnOnce(&mut MinifyOptions) -> &mut Option<T>,
+        what: &str,
+    ) -> Result<Option<&mut T>> {
+        let minifies = self.config.minify && self.minify_level == MinifyLevel::Full;
+        match section(&mut self.minify) {
+            Some(section) => Ok(Some(section)),
+            None if minifies => Err(ApplicationError::GeneralError(
+                format!("Can't {what} with the minifier section for it turned off").into(),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/// How much release builds are minified
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MinifyLevel {
+    /// Compress, mangle, and print without whitespace
+    #[default]
+    Full,
+    /// Only print without whitespace and comments
+    WhitespaceOnly,
+    /// Print like debug builds
+    None,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BoolOr<T> {
+    Bool(bool),
+    Data(T),
+}
+
+/// `SwcrcConfig::minify` in a config file
+#[derive(Deserialize)]
+struct TerserMinifyOptions {
+    compress: Option<BoolOr<TerserCompressorOptions>>,
+    mangle: Option<BoolOr<MangleOptions>>,
+    #[serde(default)]
+    keep_classnames: bool,
+    #[serde(default)]
+    keep_fnames: bool,
+    /// Ignored with a warning, older configs may still have them
+    #[serde(flatten)]
+    unknown: BTreeMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+fn terser_minify_options<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<MinifyOptions, D::Error> {
+    let options = TerserMinifyOptions::deserialize(deserializer)?;
+    let mut minify = release_minify_options();
+
+    if !options.unknown.is_empty() {
+        let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, None);
+        for key in options.unknown.keys() {
+            handler.warn(&format!("ignoring unknown minify option `{key}`"));
+        }
+    }
+
+    // `global_defs` and `pure_funcs` are parsed as expressions
+    let cm = Lrc::<SourceMap>::default();
+    match options.compress {
+        Some(BoolOr::Bool(false)) => minify.compress = None,
+        Some(BoolOr::Bool(true)) | None => {}
+        Some(BoolOr::Data(compress)) => {
+            let top_level = compress.toplevel.is_none();
+            let mut compress = compress.into_config(cm);
+            if top_level {
+                compress.top_level = minify.compress.and_then(|c| c.top_level);
+            }
+            minify.compress = Some(compress);
+        }
+    }
+    match options.mangle {
+        Some(BoolOr::Bool(false)) => minify.mangle = None,
+        Some(BoolOr::Bool(true)) | None => {}
+        Some(BoolOr::Data(mut mangle)) => {
+            if mangle.top_level.is_none() {
+                mangle.top_level = Some(true);
+            }
+            minify.mangle = Some(mangle);
+        }
+    }
+
+    if let Some(compress) = &mut minify.compress {
+        compress.keep_classnames |= options.keep_classnames;
+        compress.keep_fnames |= options.keep_fnames;
+    }
+    if let Some(mangle) = &mut minify.mangle {
+        mangle.keep_class_names |= options.keep_classnames;
+        mangle.keep_fn_names |= options.keep_fnames;
+    }
+
+    Ok(minify)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OutputConfig {
+    pub output: PathBuf,
+    pub modules: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HmrOptions {
+    /// Port of the `trunk_bundler hmr` websocket server
+    #[serde(default = "default_hmr_port")]
+    pub port: u16,
+}
+
+impl Default for HmrOptions {
+    fn default() -> Self {
+        Self {
+            port: default_hmr_port(),
+        }
+    }
+}
+
+fn default_hmr_port() -> u16 {
+    3001
+}
+
+/// A command which compiles a file to js
+///
+/// The command gets `{ "path", "contents" }` as json on stdin, and must print
+/// `{ "code", "map"?, "errors"? }` as json to stdout. `errors` is a list of `{ "message", "line"?, "column"? }`.
+/// `map` is a source map of `code`, and gets chained into the bundle's source map so it points at the original file
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExternalLoader {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
 pub enum ModuleType {
     #[default]
     #[serde(rename = "es")]
@@ -64,21 +388,84 @@ pub fn generate_config(debug: bool) -> SwcrcConfig {
     // set options for release build
     // will compress, mangle, and minify everything
     if !debug {
-        config.minify = MinifyOptions {
-            rename: true,
-            compress: Some(CompressOptions {
-                top_level: Some(TopLevelOptions { functions: true }),
-                ..Default::default()
-            }),
-            mangle: Some(MangleOptions {
-                top_level: Some(true),
-                keep_class_names: false,
-                keep_fn_names: false,
-                ..Default::default()
-            }),
-            ..Default::default()
-        }
+        config.minify = release_minify_options();
     }
 
     config
 }
+
+fn release_minify_options() -> MinifyOptions {
+    MinifyOptions {
+        rename: true,
+        compress: Some(CompressOptions {
+            top_level: Some(TopLevelOptions { functions: true }),
+            ..Default::default()
+        }),
+        mangle: Some(MangleOptions {
+            top_level: Some(true),
+            keep_class_names: false,
+            keep_fn_names: false,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swc_atoms::JsWord;
+
+    use crate::helpers::TempDir;
+
+    fn from_file(name: &str, json: &str, debug: bool) -> SwcrcConfig {
+        let dir = TempDir::new(name, &[(".swcrc", json)]);
+        SwcrcConfig::from_file(&dir.0.join(".swcrc"), debug).unwrap()
+    }
+
+    #[test]
+    fn sections_turned_off_fail_what_needs_them() {
+        let json = r#"{ "minify": { "compress": false, "mangle": false } }"#;
+
+        let mut config = from_file("config_off_release", json, false);
+        assert!(config.drop_console().is_err());
+        assert!(config.mangle_props("^_", vec![]).is_err());
+        // names are already kept without them, and they aren't turned back on
+        config.keep_names();
+        assert!(config.minify.compress.is_none() && config.minify.mangle.is_none());
+
+        // debug builds don't minify anyway
+        let mut config = from_file("config_off_debug", json, true);
+        assert!(config.drop_console().is_ok());
+        assert!(config.mangle_props("^_", vec![]).is_ok());
+    }
+
+    #[test]
+    fn release_sections_get_the_options() {
+        let mut config = from_file(
+            "config_sections",
+            r#"{ "minify": { "mangle": { "reserved": ["$inject"] } } }"#,
+            false,
+        );
+        config.drop_console().unwrap();
+        config.keep_names();
+        config.mangle_props("^_", vec!["_id".into()]).unwrap();
+
+        let compress = config.minify.compress.unwrap();
+        assert!(compress.drop_console && compress.keep_fnames);
+        let mangle = config.minify.mangle.unwrap();
+        assert!(mangle.keep_fn_names);
+        assert_eq!(mangle.reserved, [JsWord::from("$inject")]);
+        assert_eq!(mangle.props.unwrap().reserved, [JsWord::from("_id")]);
+    }
+
+    #[test]
+    fn unknown_minify_options_are_ignored() {
+        let config = from_file(
+            "config_unknown",
+            r#"{ "minify": { "compress": { "passes": 2 }, "ecma": 2020, "sourceMap": true } }"#,
+            false,
+        );
+        assert_eq!(config.minify.compress.unwrap().passes, 2);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    hash::{Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+};
+
+use anyhow::{anyhow, Context};
+use rustc_hash::{FxHashMap, FxHasher};
+use serde::{Deserialize, Serialize};
+use sourcemap::{SourceMap, SourceMapBuilder};
+use swc_common::{errors::Handler, BytePos, FileName, SourceFile, Span};
+
+use super::{loading::SourcePaths, ExternalLoader};
+
+/// Source maps of loader outputs, by the file they were loaded from
+pub type LoaderMaps = Arc<Mutex<FxHashMap<PathBuf, String>>>;
+
+/// Sent to the loader command over stdin
+#[derive(Serialize)]
+struct LoaderInput<'a> {
+    path: &'a Path,
+    contents: &'a str,
+}
+
+/// Read back from the loader command's stdout
+#[derive(Deserialize, Clone)]
+pub struct LoaderOutput {
+    #[serde(default)]
+    pub code: String,
+    pub map: Option<String>,
+    #[serde(default)]
+    pub errors: Vec<LoaderError>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoaderError {
+    pub message: String,
+    /// 1-based
+    pub line: Option<usize>,
+    /// 1-based
+    pub column: Option<usize>,
+}
+
+// results keyed by a hash of the command, file path and contents
+static CACHE: OnceLock<Mutex<FxHashMap<u64, LoaderOutput>>> = OnceLock::new();
+
+/// Compile a file with an external loader command. `fm` holds the original source,
+/// so that loader errors can be reported against it
+pub fn run_loader(
+    loader: &ExternalLoader,
+    path: &Path,
+    fm: &SourceFile,
+    handler: &Handler,
+) -> anyhow::Result<LoaderOutput> {
+    let contents = &*fm.src;
+
+    let mut hasher = FxHasher::default();
+    loader.command.hash(&mut hasher);
+    loader.args.hash(&mut hasher);
+    // loaders get the path, and can compile the same contents differently for another file
+    path.hash(&mut hasher);
+    contents.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(output) = cache.lock().unwrap().get(&hash) {
+        return Ok(output.clone());
+    }
+
+    let mut child = Command::new(&loader.command)
+        .args(&loader.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to spawn loader `{}`", loader.command))?;
+
+    // written from another thread, a loader which writes before it has read everything would
+    // block on a full stdout pipe while we block on a full stdin one
+    let input = serde_json::to_vec(&LoaderInput { path, contents })?;
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let result = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to run loader `{}`", loader.command))?;
+    writer.join().unwrap().with_context(|| {
+        format!(
+            "Failed to write {} to loader `{}`",
+            path.display(),
+            loader.command
+        )
+    })?;
+    let output = serde_json::from_slice::<LoaderOutput>(&result.stdout).with_context(|| {
+        format!(
+            "Loader `{}` returned invalid output for {}",
+            loader.command,
+            path.display()
+        )
+    })?;
+
+    if !output.errors.is_empty() {
+        for error in &output.errors {
+            match error_pos(fm, error) {
+                Some(pos) => {
+                    handler.span_err(Span::new(pos, pos, Default::default()), &error.message)
+                }
+                None => handler.err(&error.message),
+            }
+        }
+
+        return Err(anyhow!(
+            "Loader `{}` failed to compile {}",
+            loader.command,
+            path.display()
+        ));
+    }
+
+    if !result.status.success() {
+        return Err(anyhow!(
+            "Loader `{}` exited with {} for {}",
+            loader.command,
+            result.status,
+            path.display()
+        ));
+    }
+
+    cache.lock().unwrap().insert(hash, output.clone());
+
+    Ok(output)
+}
+
+fn error_pos(fm: &SourceFile, error: &LoaderError) -> Option<BytePos> {
+    let line_start = *fm.lines.get(error.line?.checked_sub(1)?)?;
+    let column = error.column.unwrap_or(1).saturating_sub(1);
+
+    Some(line_start + BytePos(column as u32))
+}
+
+/// Points the mappings of loader outputs in a bundle's source map back to the files the loader
+/// compiled them from. Mappings the loader's map has nothing for are dropped
+pub fn chain_loader_maps(
+    map: SourceMap,
+    loader_maps: &LoaderMaps,
+    paths: &SourcePaths,
+) -> anyhow::Result<SourceMap> {
+    let loader_maps = loader_maps.lock().unwrap();
+    if loader_maps.is_empty() {
+        return Ok(map);
+    }
+
+    // the bundle's map names sources the same way
+    let loader_maps = loader_maps
+        .iter()
+        .map(|(path, map)| {
+            let map = SourceMap::from_slice(map.as_bytes())
+                .with_context(|| format!("Invalid loader source map for {}", path.display()))?;
+            Ok((paths.name(&FileName::Real(path.clone())), (path, map)))
+        })
+        .collect::<anyhow::Result<FxHashMap<_, _>>>()?;
+
+    let mut builder = SourceMapBuilder::new(map.get_file());
+    for token in map.tokens() {
+        let (dst_line, dst_col) = token.get_dst();
+
+        let Some((path, loader_map)) = token.get_source().and_then(|s| loader_maps.get(s)) else {
+            let (src_line, src_col) = token.get_src();
+            builder.add(dst_line, dst_col, src_line, src_col, token.get_source(), token.get_name());
+            continue;
+        };
+
+        let Some(original) = loader_map.lookup_token(token.get_src_line(), token.get_src_col())
+        else {
+            continue;
+        };
+
+        let source = original
+            .get_source()
+            .map(|source| original_source(path, source, paths));
+        let (src_line, src_col) = original.get_src();
+        builder.add(
+            dst_line,
+            dst_col,
+            src_line,
+            src_col,
+            source.as_deref(),
+            original.get_name().or(token.get_name()),
+        );
+
+        if let Some(source) = &source {
+            let id = builder.add_source(source);
+            let contents = loader_map.get_source_contents(original.get_src_id());
+            if contents.is_some() && !builder.has_source_contents(id) {
+                builder.set_source_contents(id, contents);
+            }
+        }
+    }
+
+    Ok(builder.into_sourcemap())
+}
+
+/// Sources of a loader's map are relative to the file it compiled, and get named like the bundle's own
+fn original_source(path: &Path, source: &str, paths: &SourcePaths) -> String {
+    if source.contains("://") {
+        return source.to_owned();
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    paths.name(&FileName::Real(dir.join(source)))
+}
+
+#[cfg(test)]
+mod tests {
+    use swc_common::{errors::ColorConfig, sync::Lrc};
+
+    use super::*;
+
+    /// Runs a `sh -c` loader on `contents`
+    fn load(script: &str, path: &str, contents: &str) -> anyhow::Result<LoaderOutput> {
+        let cm = Lrc::new(swc_common::SourceMap::default());
+        let fm = cm.new_source_file(FileName::Real(path.into()), contents.to_owned());
+        let handler = Handler::with_tty_emitter(ColorConfig::Never, false, false, Some(cm));
+        let loader = ExternalLoader {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+        };
+
+        run_loader(&loader, Path::new(path), &fm, &handler)
+    }
+
+    #[test]
+    fn caches_loader_output_by_path() {
+        // compiles every file to its own path
+        let script = r#"sed 's/.*"path":"\([^"]*\)".*/{"code":"\1"}/'"#;
+
+        let a = load(script, "/project/src/a.coffee", "same").unwrap();
+        let b = load(script, "/project/src/b.coffee", "same").unwrap();
+        assert_eq!(a.code, "/project/src/a.coffee");
+        assert_eq!(b.code, "/project/src/b.coffee");
+    }
+
+    #[test]
+    fn loaders_can_write_before_reading_their_input() {
+        // more than fits in a pipe either way
+        let script =
+            r#"head -c 1000000 /dev/zero | tr '\0' ' '; echo '{"code":"done"}'; cat >/dev/null"#;
+        let contents = "x".repeat(1_000_000);
+
+        let output = load(script, "/project/src/big.coffee", &contents).unwrap();
+        assert_eq!(output.code, "done");
+    }
+
+    fn map(tokens: &[(u32, u32, u32, u32, &str)]) -> SourceMap {
+        let mut builder = SourceMapBuilder::new(None);
+        for &(dst_line, dst_col, src_line, src_col, source) in tokens {
+            builder.add(dst_line, dst_col, src_line, src_col, Some(source), None);
+        }
+        builder.into_sourcemap()
+    }
+
+    fn to_string(map: &SourceMap) -> String {
+        let mut out = vec![];
+        map.to_writer(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn chains_loader_maps_to_the_original_file() {
+        let paths = SourcePaths::new(Path::new("/project"));
+        let bundle = map(&[
+            (0, 0, 0, 0, "src/app.coffee"),
+            (1, 2, 3, 4, "src/app.coffee"),
+            (5, 0, 1, 0, "src/other.js"),
+        ]);
+
+        let loader_maps = LoaderMaps::default();
+        loader_maps.lock().unwrap().insert(
+            PathBuf::from("/project/src/app.coffee"),
+            to_string(&map(&[
+                (0, 0, 10, 1, "app.coffee"),
+                (3, 4, 20, 2, "app.coffee"),
+            ])),
+        );
+
+        let chained = chain_loader_maps(bundle, &loader_maps, &paths).unwrap();
+        let tokens = chained
+            .tokens()
+            .map(|t| (t.get_dst(), t.get_src(), t.get_source().unwrap().to_owned()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                ((0, 0), (10, 1), "src/app.coffee".to_owned()),
+                ((1, 2), (20, 2), "src/app.coffee".to_owned()),
+                ((5, 0), (1, 0), "src/other.js".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_maps_without_loader_output() {
+        let paths = SourcePaths::new(Path::new("/project"));
+        let bundle = map(&[(0, 0, 0, 0, "src/app.js")]);
+        let expected = to_string(&bundle);
+
+        let chained = chain_loader_maps(bundle, &LoaderMaps::default(), &paths).unwrap();
+        assert_eq!(to_string(&chained), expected);
+    }
+}
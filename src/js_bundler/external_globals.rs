@@ -0,0 +1,450 @@
+use anyhow::{anyhow, Error};
+use swc_atoms::JsWord;
+use swc_common::{collections::AHashMap, Mark, Span, DUMMY_SP};
+use swc_ecma_ast::{
+    BindingIdent, CallExpr, Callee, ComputedPropName, Decl, ExportNamedSpecifier, ExportSpecifier,
+    Expr, ExprOrSpread, Id, Ident, ImportSpecifier, KeyValueProp, Lit, MemberExpr, MemberProp,
+    Module, ModuleDecl, ModuleExportName, ModuleItem, NamedExport, Pat, Prop, PropName, Stmt,
+    VarDecl, VarDeclKind, VarDeclarator,
+};
+use swc_ecma_transforms::resolver;
+use swc_ecma_visit::{VisitMut, VisitMutWith};
+
+use super::loading::clear_marks;
+
+/// Rewrites imports of external modules to reads from the globals they're loaded into,
+/// e.g. `useState()` imported from "react" to `window.React.useState()`. Every use reads the
+/// global, so `let` bindings the global's module reassigns stay live.
+/// `require()` and dynamic `import()` of them are rewritten as well, and so are re-exports,
+/// except for `export *`, which is an error since what a global has isn't known until it runs
+pub struct ExternalGlobals<'a> {
+    globals: &'a AHashMap<String, String>,
+    /// The first error, reported once the whole module was visited
+    pub error: Option<Error>,
+}
+
+impl<'a> ExternalGlobals<'a> {
+    pub fn new(globals: &'a AHashMap<String, String>) -> Self {
+        Self {
+            globals,
+            error: None,
+        }
+    }
+
+    fn is_global(&self, item: &ModuleItem) -> bool {
+        let src = match item {
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => &import.src,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(NamedExport {
+                src: Some(src), ..
+            })) => src,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportAll(all)) => &all.src,
+            _ => return false,
+        };
+
+        self.globals.contains_key(&*src.value)
+    }
+}
+
+impl VisitMut for ExternalGlobals<'_> {
+    fn visit_mut_module(&mut self, module: &mut Module) {
+        if !module.body.iter().any(|item| self.is_global(item)) {
+            // only `require()` and `import()` to rewrite
+            module.visit_mut_with(&mut Rewrite {
+                globals: self.globals,
+                window: DUMMY_SP,
+                locals: AHashMap::default(),
+            });
+            return;
+        }
+
+        // uses of the imports are found by their resolved identifiers
+        let unresolved = Mark::new();
+        module.visit_mut_with(&mut resolver(unresolved, Mark::new(), true));
+
+        let mut rewrite = Rewrite {
+            globals: self.globals,
+            window: DUMMY_SP.apply_mark(unresolved),
+            locals: AHashMap::default(),
+        };
+
+        let mut reexports = 0;
+        let mut body = Vec::with_capacity(module.body.len());
+        for item in module.body.drain(..) {
+            if !self.is_global(&item) {
+                body.push(item);
+                continue;
+            }
+
+            match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                    let global = rewrite.global(&import.src.value).unwrap();
+                    for specifier in &import.specifiers {
+                        let (local, read) = match specifier {
+                            // umd globals are the module itself, there's no `default`
+                            ImportSpecifier::Default(s) => (&s.local, global.clone()),
+                            ImportSpecifier::Namespace(s) => (&s.local, global.clone()),
+                            ImportSpecifier::Named(s) => {
+                                let imported = match &s.imported {
+                                    Some(name) => export_name(name),
+                                    None => &s.local.sym,
+                                };
+                                (&s.local, named(global.clone(), imported))
+                            }
+                        };
+                        rewrite.locals.insert(local.to_id(), read);
+                    }
+                }
+
+                // `export { useState as use } from "react"` exports what's read when loaded:
+                // `var __global_0 = window.React.useState; export { __global_0 as use }`
+                ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)) => {
+                    let src = export.src.as_ref().unwrap();
+                    let global = rewrite.global(&src.value).unwrap();
+
+                    let mut specifiers = vec![];
+                    for specifier in &export.specifiers {
+                        let (read, exported) = match specifier {
+                            ExportSpecifier::Named(s) => (
+                                named(global.clone(), export_name(&s.orig)),
+                                s.exported.clone().unwrap_or(s.orig.clone()),
+                            ),
+                            ExportSpecifier::Namespace(s) => (global.clone(), s.name.clone()),
+                            ExportSpecifier::Default(s) => {
+                                (global.clone(), ModuleExportName::Ident(s.exported.clone()))
+                            }
+                        };
+
+                        let local = Ident::new(format!("__global_{reexports}").into(), DUMMY_SP);
+                        reexports += 1;
+
+                        body.push(var(local.clone(), read));
+                        specifiers.push(ExportSpecifier::Named(ExportNamedSpecifier {
+                            span: DUMMY_SP,
+                            orig: ModuleExportName::Ident(local),
+                            exported: Some(exported),
+                            is_type_only: false,
+                        }));
+                    }
+
+                    body.push(ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(
+                        NamedExport {
+                            span: export.span,
+                            specifiers,
+                            src: None,
+                            type_only: false,
+                            asserts: None,
+                        },
+                    )));
+                }
+
+                ModuleItem::ModuleDecl(ModuleDecl::ExportAll(all)) => {
+                    self.error.get_or_insert(anyhow!(
+                        "`export * from \"{}\"` can't be bundled, \"{}\" is the global `{}`. Export its names one by one",
+                        all.src.value,
+                        all.src.value,
+                        self.globals[&*all.src.value],
+                    ));
+                }
+
+                _ => unreachable!(),
+            }
+        }
+        module.body = body;
+
+        module.visit_mut_with(&mut rewrite);
+        clear_marks(module);
+    }
+}
+
+struct Rewrite<'a> {
+    globals: &'a AHashMap<String, String>,
+    /// Marks `window` as the global, so hygiene doesn't rename it away from a local `window`
+    window: Span,
+    /// What each import of a global is read as
+    locals: AHashMap<Id, Box<Expr>>,
+}
+
+impl Rewrite<'_> {
+    fn global(&self, module: &str) -> Option<Box<Expr>> {
+        let global = self.globals.get(module)?;
+        let window = Box::new(Expr::Ident(Ident::new("window".into(), self.window)));
+
+        Some(global.split('.').fold(window, member))
+    }
+}
+
+impl VisitMut for Rewrite<'_> {
+    fn visit_mut_module_items(&mut self, items: &mut Vec<ModuleItem>) {
+        items.visit_mut_children_with(self);
+
+        // `export { useState }` can't export a member read, it exports what's read when loaded
+        let mut exported = vec![];
+        for item in items.iter() {
+            let ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)) = item else {
+                continue;
+            };
+            if export.src.is_some() {
+                continue;
+            }
+            for specifier in &export.specifiers {
+                let ExportSpecifier::Named(s) = specifier else {
+                    continue;
+                };
+                let ModuleExportName::Ident(orig) = &s.orig else {
+                    continue;
+                };
+                if let Some(read) = self.locals.remove(&orig.to_id()) {
+                    exported.push(var(orig.clone(), read));
+                }
+            }
+        }
+        items.splice(0..0, exported);
+    }
+
+    fn visit_mut_prop(&mut self, prop: &mut Prop) {
+        prop.visit_mut_children_with(self);
+
+        // `{ useState }` to `{ useState: window.React.useState }`
+        if let Prop::Shorthand(ident) = prop {
+            if let Some(read) = self.locals.get(&ident.to_id()) {
+                *prop = Prop::KeyValue(KeyValueProp {
+                    key: PropName::Ident(Ident::new(ident.sym.clone(), DUMMY_SP)),
+                    value: read.clone(),
+                });
+            }
+        }
+    }
+
+    fn visit_mut_expr(&mut self, expr: &mut Expr) {
+        expr.visit_mut_children_with(self);
+
+        if let Expr::Ident(ident) = expr {
+            if let Some(read) = self.locals.get(&ident.to_id()) {
+                *expr = *read.clone();
+            }
+            return;
+        }
+
+        let Expr::Call(CallExpr { callee, args, .. }) = expr else {
+            return;
+        };
+        let Some(Expr::Lit(Lit::Str(src))) = args.first().map(|a| &*a.expr) else {
+            return;
+        };
+        let Some(global) = self.global(&src.value) else {
+            return;
+        };
+
+        match callee {
+            Callee::Expr(callee) if matches!(&**callee, Expr::Ident(i) if &*i.sym == "require") => {
+                *expr = *global;
+            }
+            Callee::Import(_) => {
+                let resolve = member(
+                    Box::new(Expr::Ident(Ident::new("Promise".into(), self.window))),
+                    "resolve",
+                );
+                *expr = Expr::Call(CallExpr {
+                    span: DUMMY_SP,
+                    callee: Callee::Expr(resolve),
+                    args: vec![ExprOrSpread {
+                        spread: None,
+                        expr: global,
+                    }],
+                    type_args: None,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+fn member(obj: Box<Expr>, prop: &str) -> Box<Expr> {
+    Box::new(Expr::Member(MemberExpr {
+        span: DUMMY_SP,
+        obj,
+        prop: MemberProp::Ident(Ident::new(prop.into(), DUMMY_SP)),
+    }))
+}
+
+/// What `name` is imported as. Umd globals are the module itself, `default` is the global
+fn named(global: Box<Expr>, name: &str) -> Box<Expr> {
+    match name {
+        "default" => global,
+        name => read(global, name),
+    }
+}
+
+/// `obj.name`, or `obj["name"]` when it isn't an identifier
+fn read(obj: Box<Expr>, name: &str) -> Box<Expr> {
+    let mut chars = name.chars();
+    let is_ident =
+        chars.next().is_some_and(Ident::is_valid_start) && chars.all(Ident::is_valid_continue);
+    if is_ident {
+        return member(obj, name);
+    }
+
+    Box::new(Expr::Member(MemberExpr {
+        span: DUMMY_SP,
+        obj,
+        prop: MemberProp::Computed(ComputedPropName {
+            span: DUMMY_SP,
+            expr: Box::new(Expr::Lit(Lit::Str(name.into()))),
+        }),
+    }))
+}
+
+fn export_name(name: &ModuleExportName) -> &JsWord {
+    match name {
+        ModuleExportName::Ident(i) => &i.sym,
+        ModuleExportName::Str(s) => &s.value,
+    }
+}
+
+fn var(local: Ident, init: Box<Expr>) -> ModuleItem {
+    ModuleItem::Stmt(Stmt::Decl(Decl::Var(Box::new(VarDecl {
+        span: DUMMY_SP,
+        kind: VarDeclKind::Var,
+        declare: false,
+        decls: vec![VarDeclarator {
+            span: DUMMY_SP,
+            name: Pat::Ident(BindingIdent {
+                id: local,
+                type_ann: None,
+            }),
+            init: Some(init),
+            definite: false,
+        }],
+    }))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use swc_common::{FileName, SourceMap, GLOBALS};
+    use swc_ecma_codegen::{text_writer::JsWriter, Emitter};
+    use swc_ecma_parser::parse_file_as_module;
+
+    use super::*;
+
+    /// `source` with `globals` rewritten, minified for easier comparisons
+    fn rewrite(source: &str, globals: &[(&str, &str)]) -> String {
+        try_rewrite(source, globals).unwrap()
+    }
+
+    fn try_rewrite(source: &str, globals: &[(&str, &str)]) -> Result<String, Error> {
+        let cm = Arc::new(SourceMap::default());
+        let fm = cm.new_source_file(FileName::Anon, source.to_owned());
+        let mut module = parse_file_as_module(
+            &fm,
+            Default::default(),
+            Default::default(),
+            None,
+            &mut vec![],
+        )
+        .unwrap();
+
+        let globals = globals
+            .iter()
+            .map(|&(module, global)| (module.to_owned(), global.to_owned()))
+            .collect();
+        let mut external_globals = ExternalGlobals::new(&globals);
+        GLOBALS.set(&Default::default(), || {
+            module.visit_mut_with(&mut external_globals);
+        });
+        if let Some(error) = external_globals.error {
+            return Err(error);
+        }
+
+        let mut output = vec![];
+        let mut emitter = Emitter {
+            cfg: swc_ecma_codegen::Config {
+                minify: true,
+                ..Default::default()
+            },
+            cm: cm.clone(),
+            comments: None,
+            wr: JsWriter::new(cm, "\n", &mut output, None),
+        };
+        emitter.emit_module(&module).unwrap();
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn reads_live_bindings_from_the_global() {
+        let code = rewrite(
+            r#"
+            import { count, inc } from "./common.js";
+            inc();
+            console.log(count, { count });
+            "#,
+            &[("./common.js", "__trunk_common")],
+        );
+        assert_eq!(
+            code,
+            "window.__trunk_common.inc();console.log(window.__trunk_common.count,{count:window.__trunk_common.count});"
+        );
+    }
+
+    #[test]
+    fn reads_the_global_of_shadowed_names() {
+        let code = rewrite(
+            r#"
+            import { count } from "./common.js";
+            function f(count) { return count; }
+            const window = 1;
+            f(count);
+            "#,
+            &[("./common.js", "__trunk_common")],
+        );
+        assert!(
+            code.contains("f(window.__trunk_common.count)") && code.contains("return count"),
+            "{code}"
+        );
+        assert!(!code.contains("const window="), "{code}");
+    }
+
+    #[test]
+    fn reads_names_which_are_not_identifiers_as_computed_props() {
+        let code = rewrite(
+            r#"
+            import { "kebab-name" as kebab, default as lib, valid } from "lib";
+            kebab(lib, valid);
+            "#,
+            &[("lib", "Lib")],
+        );
+        assert_eq!(
+            code,
+            r#"window.Lib["kebab-name"](window.Lib,window.Lib.valid);"#
+        );
+    }
+
+    #[test]
+    fn reexports_what_the_global_has_when_loaded() {
+        let code = rewrite(
+            r#"
+            export { useState, "kebab-name" as kebab } from "react";
+            export * as React from "react";
+            "#,
+            &[("react", "React")],
+        );
+        assert_eq!(
+            code,
+            r#"var __global_0=window.React.useState;var __global_1=window.React["kebab-name"];export{__global_0 as useState,__global_1 as kebab};var __global_2=window.React;export{__global_2 as React};"#
+        );
+    }
+
+    #[test]
+    fn star_reexports_of_globals_are_an_error() {
+        let error = try_rewrite(r#"export * from "react";"#, &[("react", "React")]).unwrap_err();
+        assert!(
+            error.to_string().contains("`export * from \"react\"`"),
+            "{error}"
+        );
+
+        // other modules are left to the bundler
+        let code = try_rewrite(r#"export * from "./local";"#, &[("react", "React")]).unwrap();
+        assert_eq!(code, r#"export*from"./local";"#);
+    }
+}
@@ -0,0 +1,318 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Error};
+use glob::Pattern;
+use swc_common::DUMMY_SP;
+use swc_ecma_ast::{
+    CallExpr, Callee, Expr, ExprOrSpread, Ident, ImportDecl, ImportDefaultSpecifier,
+    ImportNamedSpecifier, ImportSpecifier, ImportStarAsSpecifier, KeyValueProp, Lit, MemberExpr,
+    MemberProp, MetaPropExpr, MetaPropKind, Module, ModuleDecl, ModuleExportName, ModuleItem,
+    ObjectLit, Prop, PropName, PropOrSpread, Str,
+};
+use swc_ecma_visit::{VisitMut, VisitMutWith};
+
+/// Expands `import.meta.glob("./pages/*.tsx", { eager: true })` into an object of the matching
+/// modules, keyed by their path relative to the importing module. `{ import: "default" }` picks
+/// a single export.
+///
+/// `swc_bundler` doesn't split out dynamic imports, so the matched modules are imported
+/// statically, and globs without `eager: true` are an error instead of pretending to be lazy
+pub struct ImportGlob<'a> {
+    pub path: &'a Path,
+    imports: Vec<ImportDecl>,
+    /// How many globs were expanded, to name their imports
+    globs: usize,
+    /// The first error, reported once the whole module was visited
+    pub error: Option<Error>,
+}
+
+impl<'a> ImportGlob<'a> {
+    pub fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            imports: vec![],
+            globs: 0,
+            error: None,
+        }
+    }
+
+    fn expand(&mut self, args: &[ExprOrSpread]) -> Result<Expr, Error> {
+        let patterns = match args.first().map(|a| &*a.expr) {
+            Some(Expr::Lit(Lit::Str(s))) => vec![s.value.to_string()],
+            Some(Expr::Array(array)) => array
+                .elems
+                .iter()
+                .map(|e| match e.as_ref().map(|e| &*e.expr) {
+                    Some(Expr::Lit(Lit::Str(s))) => Ok(s.value.to_string()),
+                    _ => Err(anyhow!(
+                        "`import.meta.glob` patterns have to be string literals"
+                    )),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => {
+                return Err(anyhow!(
+                    "`import.meta.glob` patterns have to be string literals"
+                ))
+            }
+        };
+
+        let mut eager = false;
+        let mut export = None;
+        if let Some(Expr::Object(options)) = args.get(1).map(|a| &*a.expr) {
+            for prop in &options.props {
+                let PropOrSpread::Prop(prop) = prop else { continue };
+                let Prop::KeyValue(KeyValueProp { key: PropName::Ident(key), value }) = &**prop else {
+                    continue;
+                };
+
+                match (&*key.sym, &**value) {
+                    ("eager", Expr::Lit(Lit::Bool(b))) => eager = b.value,
+                    ("import", Expr::Lit(Lit::Str(s))) => export = Some(s.value.clone()),
+                    _ => {
+                        return Err(anyhow!(
+                            "unsupported `import.meta.glob` option `{}`",
+                            key.sym
+                        ))
+                    }
+                }
+            }
+        }
+
+        if !eager {
+            return Err(anyhow!(
+                "`import.meta.glob` needs `{{ eager: true }}`, bundles aren't split so its modules can't be loaded lazily"
+            ));
+        }
+
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let files = self.files(dir, &patterns)?;
+
+        let n = self.globs;
+        self.globs += 1;
+        let mut props = vec![];
+        for (i, file) in files.iter().enumerate() {
+            let key = relative(dir, file);
+            let local = Ident::new(format!("__glob_{n}_{i}").into(), DUMMY_SP);
+
+            let specifier = match &export {
+                Some(name) if name == "default" => {
+                    ImportSpecifier::Default(ImportDefaultSpecifier {
+                        span: DUMMY_SP,
+                        local: local.clone(),
+                    })
+                }
+                Some(name) => ImportSpecifier::Named(ImportNamedSpecifier {
+                    span: DUMMY_SP,
+                    local: local.clone(),
+                    imported: Some(ModuleExportName::Ident(Ident::new(name.clone(), DUMMY_SP))),
+                    is_type_only: false,
+                }),
+                None => ImportSpecifier::Namespace(ImportStarAsSpecifier {
+                    span: DUMMY_SP,
+                    local: local.clone(),
+                }),
+            };
+            self.imports.push(ImportDecl {
+                span: DUMMY_SP,
+                specifiers: vec![specifier],
+                src: Box::new(Str::from(&*key)),
+                type_only: false,
+                asserts: None,
+            });
+
+            props.push(PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
+                key: PropName::Str(Str::from(&*key)),
+                value: Box::new(Expr::Ident(local)),
+            }))));
+        }
+
+        Ok(Expr::Object(ObjectLit {
+            span: DUMMY_SP,
+            props,
+        }))
+    }
+
+    /// Files matching the patterns, sorted, without the importing module itself.
+    /// Patterns starting with `!` exclude what they match
+    fn files(&self, dir: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, Error> {
+        let excluded = patterns
+            .iter()
+            .filter_map(|p| p.strip_prefix('!'))
+            .map(|p| Pattern::new(&dir.join(p).display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut files = vec![];
+        for pattern in patterns.iter().filter(|p| !p.starts_with('!')) {
+            for file in glob::glob(&dir.join(pattern).display().to_string())? {
+                let file = file?;
+                let is_excluded = excluded.iter().any(|e| e.matches_path(&file));
+
+                if file.is_file() && !is_excluded && file != self.path && !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+}
+
+impl VisitMut for ImportGlob<'_> {
+    fn visit_mut_module(&mut self, module: &mut Module) {
+        module.visit_mut_children_with(self);
+
+        let imports = self
+            .imports
+            .drain(..)
+            .map(|i| ModuleItem::ModuleDecl(ModuleDecl::Import(i)));
+        module.body.splice(0..0, imports);
+    }
+
+    fn visit_mut_expr(&mut self, expr: &mut Expr) {
+        expr.visit_mut_children_with(self);
+
+        let Expr::Call(CallExpr {
+            callee: Callee::Expr(callee),
+            args,
+            ..
+        }) = expr
+        else {
+            return;
+        };
+        let Expr::Member(MemberExpr { obj, prop: MemberProp::Ident(prop), .. }) = &**callee else {
+            return;
+        };
+        let is_import_meta = matches!(
+            &**obj,
+            Expr::MetaProp(MetaPropExpr {
+                kind: MetaPropKind::ImportMeta,
+                ..
+            })
+        );
+        if !is_import_meta || &*prop.sym != "glob" {
+            return;
+        }
+
+        match self.expand(args) {
+            Ok(object) => *expr = object,
+            Err(e) => {
+                self.error
+                    .get_or_insert(anyhow!("{}: {e}", self.path.display()));
+            }
+        }
+    }
+}
+
+/// Import specifier for `file`, relative to `dir`, e.g. `./pages/a.tsx` or `../shared/b.ts`
+pub(super) fn relative(dir: &Path, file: &Path) -> String {
+    let dir = dir.components().collect::<Vec<_>>();
+    let file = file.components().collect::<Vec<_>>();
+    let common = dir.iter().zip(&file).take_while(|(a, b)| a == b).count();
+
+    let parents = dir[common..]
+        .iter()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .map(|_| "..".to_owned());
+    let rest = file[common..]
+        .iter()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned());
+    let parts = parents.chain(rest).collect::<Vec<_>>();
+
+    if parts.first().is_some_and(|p| p == "..") {
+        parts.join("/")
+    } else {
+        format!("./{}", parts.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn specifiers_are_relative_to_the_importing_dir() {
+        let relative = |dir: &str, file: &str| relative(Path::new(dir), Path::new(file));
+
+        assert_eq!(
+            relative("/app/src", "/app/src/pages/a.tsx"),
+            "./pages/a.tsx"
+        );
+        assert_eq!(relative("/app/src", "/app/src/b.js"), "./b.js");
+        assert_eq!(
+            relative("/app/src/pages", "/app/src/shared/b.ts"),
+            "../shared/b.ts"
+        );
+        assert_eq!(
+            relative("/app/src/a/b", "/app/lib/c.js"),
+            "../../../lib/c.js"
+        );
+        assert_eq!(relative("/app", "/other/d.js"), "../other/d.js");
+    }
+
+    #[test]
+    fn matches_files_without_excluded_ones_or_the_importer() {
+        let dir = TempDir::new(
+            "import_glob_files",
+            &[
+                ("pages/index.js", ""),
+                ("pages/b.js", ""),
+                ("pages/a.js", ""),
+                ("pages/a.test.js", ""),
+                ("pages/nested/c.js", ""),
+            ],
+        );
+        let importer = dir.0.join("pages/index.js");
+        let glob = ImportGlob::new(&importer);
+
+        let files = glob
+            .files(
+                &dir.0,
+                &[
+                    "pages/*.js".to_owned(),
+                    "pages/**/*.js".to_owned(),
+                    "!pages/*.test.js".to_owned(),
+                ],
+            )
+            .unwrap();
+        let files = files
+            .iter()
+            .map(|f| relative(&dir.0, f))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            files,
+            ["./pages/a.js", "./pages/b.js", "./pages/nested/c.js"]
+        );
+    }
+
+    #[test]
+    fn globs_have_to_be_eager() {
+        let dir = TempDir::new("import_glob_eager", &[("pages/a.js", "")]);
+        let importer = dir.0.join("index.js");
+        let pattern = ExprOrSpread {
+            spread: None,
+            expr: Box::new(Expr::Lit(Lit::Str(Str::from("./pages/*.js")))),
+        };
+        let eager = ExprOrSpread {
+            spread: None,
+            expr: Box::new(Expr::Object(ObjectLit {
+                span: DUMMY_SP,
+                props: vec![PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
+                    key: PropName::Ident(Ident::new("eager".into(), DUMMY_SP)),
+                    value: Box::new(Expr::Lit(Lit::Bool(true.into()))),
+                })))],
+            })),
+        };
+
+        let mut glob = ImportGlob::new(&importer);
+        assert!(glob.expand(&[pattern.clone()]).is_err());
+
+        let Expr::Object(object) = glob.expand(&[pattern, eager]).unwrap() else {
+            panic!("not an object");
+        };
+        assert_eq!(object.props.len(), 1);
+        assert_eq!(glob.imports.len(), 1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt::Write;
+
+use swc_common::{
+    comments::{Comment, CommentKind},
+    BytePos, FileName, SourceMap, Span,
+};
+use swc_ecma_ast::Module;
+use swc_ecma_visit::{Visit, VisitWith};
+use swc_node_comments::SwcComments;
+
+use super::package::Packages;
+
+/// Whether a comment has to be kept for licensing: `/*!`, `//!`, or one with `@license` or `@preserve`
+pub fn is_legal(comment: &Comment) -> bool {
+    comment.text.starts_with('!')
+        || comment.text.contains("@license")
+        || comment.text.contains("@preserve")
+}
+
+/// A comment as it's written in the source
+pub fn to_source(comment: &Comment) -> String {
+    match comment.kind {
+        CommentKind::Block => format!("/*{}*/", comment.text),
+        CommentKind::Line => format!("//{}", comment.text),
+    }
+}
+
+/// Legal comments and npm packages which went into an output file
+#[derive(Default)]
+pub struct Notices {
+    comments: Vec<String>,
+    /// `name@version (license)`
+    packages: Vec<String>,
+}
+
+impl Notices {
+    /// Add what a bundled module is made of, going by the source files its code comes from
+    pub fn collect(
+        &mut self,
+        module: &Module,
+        cm: &SourceMap,
+        comments: &SwcComments,
+        packages: &Packages,
+    ) {
+        let mut positions = Positions::default();
+        module.visit_with(&mut positions);
+        let mut positions = positions.0;
+        positions.sort();
+        positions.dedup();
+
+        // by name, as files are loaded in any order
+        let mut files = cm
+            .files()
+            .iter()
+            .filter(|f| {
+                let i = positions.partition_point(|p| *p < f.start_pos);
+                positions.get(i).is_some_and(|p| *p < f.end_pos)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        files.sort_by_key(|f| f.name.to_string());
+
+        for file in files {
+            let mut file_comments = comments
+                .leading
+                .iter()
+                .chain(comments.trailing.iter())
+                .filter(|c| *c.key() >= file.start_pos && *c.key() < file.end_pos)
+                .flat_map(|c| {
+                    c.value()
+                        .iter()
+                        .map(|c| (c.span.lo, c.clone()))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            file_comments.sort_by_key(|(pos, _)| *pos);
+
+            self.add_comments(file_comments.iter().map(|(_, c)| c));
+
+            if let FileName::Real(path) = &file.name {
+                if let Some(package) = packages.notice(path) {
+                    if !self.packages.contains(&package) {
+                        self.packages.push(package);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn add_comments<'a>(&mut self, comments: impl IntoIterator<Item = &'a Comment>) {
+        for comment in comments {
+            let comment = to_source(comment);
+            if !self.comments.contains(&comment) {
+                self.comments.push(comment);
+            }
+        }
+    }
+
+    /// The legal comments, as they're written in the source
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// The `.LICENSE.txt` file, with the legal comments and the npm packages. `None` if both are empty
+    pub fn license_file(&self) -> Option<Vec<u8>> {
+        if self.comments.is_empty() && self.packages.is_empty() {
+            return None;
+        }
+
+        let mut file = self.comments.join("\n\n");
+        if !self.packages.is_empty() {
+            if !file.is_empty() {
+                file.push_str("\n\n");
+            }
+
+            let mut packages = self.packages.clone();
+            packages.sort();
+            let _ = write!(file, "Bundled npm packages:\n\n{}", packages.join("\n"));
+        }
+        file.push('\n');
+
+        Some(file.into_bytes())
+    }
+}
+
+/// Start of every span in a module
+#[derive(Default)]
+struct Positions(Vec<BytePos>);
+
+impl Visit for Positions {
+    fn visit_span(&mut self, span: &Span) {
+        if !span.is_dummy() {
+            self.0.push(span.lo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use swc_common::comments::Comments;
+    use swc_ecma_parser::parse_file_as_module;
+
+    use super::*;
+    use crate::helpers::TempDir;
+
+    fn license_file(notices: &Notices) -> Option<String> {
+        notices
+            .license_file()
+            .map(|file| String::from_utf8(file).unwrap())
+    }
+
+    /// Notices of the given files, parsed into one source map like a bundle's modules. Only
+    /// legal comments are kept, as the loader does
+    fn collect(files: &[PathBuf]) -> Notices {
+        let cm = SourceMap::default();
+        let legal_comments = SwcComments::default();
+        let packages = Packages::default();
+
+        let mut notices = Notices::default();
+        for file in files {
+            let fm = cm.load_file(file).unwrap();
+            let comments = SwcComments::default();
+            let module = parse_file_as_module(
+                &fm,
+                Default::default(),
+                Default::default(),
+                Some(&comments),
+                &mut vec![],
+            )
+            .unwrap();
+            for entry in comments.leading.iter() {
+                let legal = entry.value().iter().filter(|c| is_legal(c)).cloned();
+                legal_comments.add_leading_comments(*entry.key(), legal.collect());
+            }
+
+            notices.collect(&module, &cm, &legal_comments, &packages);
+        }
+
+        notices
+    }
+
+    #[test]
+    fn license_files_list_comments_and_packages() {
+        let dir = TempDir::new(
+            "legal_notices",
+            &[
+                (
+                    "src/app.js",
+                    "/*! app v1 */\n// not legal\nexport const app = 1;",
+                ),
+                (
+                    "node_modules/lib/package.json",
+                    r#"{ "name": "lib", "version": "2.0.0", "license": "MIT" }"#,
+                ),
+                (
+                    "node_modules/lib/index.js",
+                    "/** @license lib */\nexport const lib = 2;",
+                ),
+            ],
+        );
+
+        let notices = collect(&[dir.0.join("src/app.js")]);
+        assert_eq!(notices.comments(), ["/*! app v1 */"]);
+        assert_eq!(license_file(&notices).unwrap(), "/*! app v1 */\n");
+
+        let notices = collect(&[
+            dir.0.join("node_modules/lib/index.js"),
+            dir.0.join("src/app.js"),
+        ]);
+        assert_eq!(
+            license_file(&notices).unwrap(),
+            "/** @license lib */\n\n/*! app v1 */\n\nBundled npm packages:\n\nlib@2.0.0 (MIT)\n"
+        );
+    }
+
+    #[test]
+    fn license_files_of_packages_without_comments() {
+        let dir = TempDir::new(
+            "legal_packages_only",
+            &[
+                (
+                    "node_modules/lib/package.json",
+                    r#"{ "name": "lib", "license": { "type": "ISC" } }"#,
+                ),
+                ("node_modules/lib/index.js", "export const lib = 2;"),
+            ],
+        );
+
+        let notices = collect(&[dir.0.join("node_modules/lib/index.js")]);
+        assert_eq!(
+            license_file(&notices).unwrap(),
+            "Bundled npm packages:\n\nlib@unknown (ISC)\n"
+        );
+    }
+
+    #[test]
+    fn no_license_file_without_notices() {
+        let dir = TempDir::new(
+            "legal_nothing",
+            &[("src/app.js", "// just a comment\nexport const app = 1;")],
+        );
+
+        assert_eq!(license_file(&collect(&[dir.0.join("src/app.js")])), None);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::errors::{ApplicationError, Result};
+
+/// Lists the lib folders and files, in the order they're added
+pub const LIB_MANIFEST: &str = "manifest.json";
+
+/// Files of the `lib` directory which go into a bundle
+#[derive(Debug, Default)]
+pub struct Libs {
+    /// Scripts prepended to the bundle
+    pub js: Vec<PathBuf>,
+    /// Stylesheets, bundled into a css file next to it
+    pub css: Vec<PathBuf>,
+}
+
+impl Libs {
+    /// Lib files of the `lib` directory, in the order of `lib/manifest.json` (a list of folder and
+    /// file names), or by name without one. `selected` picks some of them, and libs which
+    /// aren't in the manifest are added after the others, in the order they're selected
+    pub fn find(lib_dir: &Path, selected: Option<&[String]>) -> Result<Self> {
+        let mut libs = Self::default();
+        if !lib_dir.is_dir() {
+            return match selected.and_then(|s| s.first()) {
+                Some(name) => Err(ApplicationError::LibNotFound(
+                    name.clone(),
+                    vec![lib_dir.join(name)],
+                )),
+                None => Ok(libs),
+            };
+        }
+
+        let manifest = lib_dir.join(LIB_MANIFEST);
+        let listed = if manifest.is_file() {
+            let json = fs::read_to_string(&manifest)?;
+            serde_json::from_str::<Vec<String>>(&json).map_err(|e| {
+                ApplicationError::GeneralError(format!("{}: {e}", manifest.display()).into())
+            })?
+        } else {
+            let mut names = fs::read_dir(lib_dir)?
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let names = match selected {
+            Some(selected) => {
+                let mut names = listed
+                    .iter()
+                    .filter(|l| selected.iter().any(|s| is_lib(l, s)))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                names.extend(
+                    selected
+                        .iter()
+                        .filter(|s| !listed.iter().any(|l| is_lib(l, s)))
+                        .cloned(),
+                );
+                names
+            }
+            None => listed,
+        };
+
+        for name in names {
+            let path = lib_dir.join(&name);
+            let candidates = [path, lib_dir.join(format!("{name}.js"))];
+            let Some(path) = candidates.iter().find(|p| p.exists()) else {
+                return Err(ApplicationError::LibNotFound(name, candidates.to_vec()));
+            };
+
+            // directory listings come in any order
+            for entry in WalkDir::new(path).sort_by_file_name().into_iter().flatten() {
+                let file = entry.path();
+                if !file.is_file() || file == manifest {
+                    continue;
+                }
+
+                match file.extension().and_then(|e| e.to_str()) {
+                    Some("js") => libs.js.push(file.to_owned()),
+                    Some("css") => libs.css.push(file.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(libs)
+    }
+}
+
+/// Whether a lib of the manifest is the selected one. `jquery` selects `jquery.js` as well
+fn is_lib(listed: &str, selected: &str) -> bool {
+    let listed = listed.trim_end_matches('/');
+    listed == selected || listed.strip_suffix(".js") == Some(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    /// Lib files relative to `lib_dir`
+    fn names(files: &[PathBuf], lib_dir: &Path) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.strip_prefix(lib_dir).unwrap().display().to_string())
+            .collect()
+    }
+
+    fn lib_dir(name: &str) -> TempDir {
+        TempDir::new(
+            name,
+            &[
+                (
+                    "lib/manifest.json",
+                    r#"["jquery.js", "bootstrap/", "app.css"]"#,
+                ),
+                ("lib/jquery.js", ""),
+                ("lib/bootstrap/b.js", ""),
+                ("lib/bootstrap/a.js", ""),
+                ("lib/bootstrap/bootstrap.css", ""),
+                ("lib/app.css", ""),
+                ("lib/extra.js", ""),
+                ("lib/readme.txt", ""),
+            ],
+        )
+    }
+
+    #[test]
+    fn orders_libs_like_the_manifest() {
+        let dir = lib_dir("libs_manifest");
+        let lib = dir.0.join("lib");
+
+        let libs = Libs::find(&lib, None).unwrap();
+        assert_eq!(
+            names(&libs.js, &lib),
+            ["jquery.js", "bootstrap/a.js", "bootstrap/b.js"]
+        );
+        assert_eq!(
+            names(&libs.css, &lib),
+            ["bootstrap/bootstrap.css", "app.css"]
+        );
+    }
+
+    #[test]
+    fn orders_libs_by_name_without_a_manifest() {
+        let dir = TempDir::new(
+            "libs_no_manifest",
+            &[("lib/b.js", ""), ("lib/a/z.js", ""), ("lib/c.css", "")],
+        );
+        let lib = dir.0.join("lib");
+
+        let libs = Libs::find(&lib, None).unwrap();
+        assert_eq!(names(&libs.js, &lib), ["a/z.js", "b.js"]);
+        assert_eq!(names(&libs.css, &lib), ["c.css"]);
+    }
+
+    #[test]
+    fn selects_libs_with_and_without_js() {
+        let dir = lib_dir("libs_selected");
+        let lib = dir.0.join("lib");
+
+        // in manifest order, then the unlisted ones in the order they're selected
+        let selected = ["extra", "bootstrap", "jquery.js"].map(String::from);
+        let libs = Libs::find(&lib, Some(&selected)).unwrap();
+        assert_eq!(
+            names(&libs.js, &lib),
+            ["jquery.js", "bootstrap/a.js", "bootstrap/b.js", "extra.js"]
+        );
+        assert_eq!(names(&libs.css, &lib), ["bootstrap/bootstrap.css"]);
+
+        let libs = Libs::find(&lib, Some(&["jquery".to_owned()])).unwrap();
+        assert_eq!(names(&libs.js, &lib), ["jquery.js"]);
+    }
+
+    #[test]
+    fn missing_libs_list_the_tried_paths() {
+        let dir = lib_dir("libs_missing");
+        let lib = dir.0.join("lib");
+
+        let error = Libs::find(&lib, Some(&["missing".to_owned()])).unwrap_err();
+        let ApplicationError::LibNotFound(name, tried) = error else {
+            panic!("{error}");
+        };
+        assert_eq!(name, "missing");
+        assert_eq!(tried, [lib.join("missing"), lib.join("missing.js")]);
+
+        // selecting a lib without a `lib` directory
+        let error = Libs::find(&dir.0.join("none"), Some(&["jquery".to_owned()])).unwrap_err();
+        assert!(matches!(error, ApplicationError::LibNotFound(name, _) if name == "jquery"));
+        assert!(Libs::find(&dir.0.join("none"), None).is_ok());
+    }
+}
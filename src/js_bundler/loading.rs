@@ -1,19 +1,85 @@
-use swc_atoms::js_word;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+
+use swc::config::PluginConfig;
+use swc_atoms::{js_word, JsWord};
 use swc_bundler::{Load, ModuleData, ModuleRecord};
 use swc_common::{
     collections::AHashMap,
-    comments::SingleThreadedComments,
+    comments::{CommentKind, Comments, SingleThreadedComments},
     errors::{ColorConfig, Handler},
+    source_map::SourceMapGenConfig,
     sync::Lrc,
-    FileName, Mark, SourceMap, Span,
+    FileName, Mark, SourceMap, Span, SyntaxContext,
 };
-use swc_ecma_ast::{EsVersion, KeyValueProp};
+use swc_ecma_ast::{EsVersion, Ident, KeyValueProp, Module};
+use swc_ecma_loader::resolve::Resolve;
 use swc_ecma_parser::{parse_file_as_module, EsConfig, Syntax, TsConfig};
-use swc_ecma_visit::FoldWith;
+use swc_ecma_transforms::{hygiene, resolver};
+use swc_ecma_transforms_react::Options as JsxOptions;
+use swc_ecma_visit::{FoldWith, VisitMut, VisitMutWith};
+use swc_node_comments::SwcComments;
 
-use super::globals::Variables;
+use super::{
+    external::{run_loader, LoaderMaps},
+    external_globals::ExternalGlobals,
+    globals::Variables,
+    import_glob::{relative as relative_path, ImportGlob},
+    legal::is_legal,
+    package::{stub_empty_imports, Packages, EMPTY_MODULE},
+    refresh::{module_id, RefreshIds, Registry, HMR_PRELUDE_SOURCE},
+    shims::{globals_prelude, stub_source},
+    side_effects::{Reexports, SideEffectFreeImports},
+    wasm_plugins::apply_plugins,
+    workspace::is_dependency,
+    ExternalLoader, Plugin,
+};
 
-pub struct Hook;
+/// Names source files relative to the project root, so bundles don't depend on where it's checked out
+#[derive(Clone)]
+pub struct SourcePaths {
+    root: PathBuf,
+    canonical: PathBuf,
+}
+
+impl SourcePaths {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            canonical: fs::canonicalize(root).unwrap_or(root.to_owned()),
+        }
+    }
+
+    pub fn name(&self, file: &FileName) -> String {
+        let FileName::Real(path) = file else {
+            return file.to_string();
+        };
+
+        let relative = path
+            .strip_prefix(&self.root)
+            .or_else(|_| path.strip_prefix(&self.canonical));
+        match relative {
+            Ok(path) => path.to_string_lossy().replace('\\', "/"),
+            // workspace packages outside of the project
+            Err(_) => relative_path(&self.canonical, path),
+        }
+    }
+}
+
+impl SourceMapGenConfig for &SourcePaths {
+    fn file_name_to_source(&self, f: &FileName) -> String {
+        self.name(f)
+    }
+}
+
+pub struct Hook {
+    pub paths: SourcePaths,
+}
 
 impl swc_bundler::Hook for Hook {
     fn get_import_meta_props(
@@ -23,7 +89,7 @@ impl swc_bundler::Hook for Hook {
     ) -> std::result::Result<Vec<KeyValueProp>, anyhow::Error> {
         use swc_ecma_ast::*;
 
-        let file_name = module_record.file_name.to_string();
+        let file_name = self.paths.name(&module_record.file_name);
 
         Ok(vec![
             KeyValueProp {
@@ -53,29 +119,130 @@ impl swc_bundler::Hook for Hook {
     }
 }
 
+/// The bundler runs its own resolver, so passes which need resolved identifiers clear the marks
+/// afterwards. Names are made unique first, so no two bindings end up with the same identifier
+pub(super) fn clear_marks(module: &mut Module) {
+    module.visit_mut_with(&mut hygiene());
+    module.visit_mut_with(&mut ClearMarks);
+}
+
+struct ClearMarks;
+
+impl VisitMut for ClearMarks {
+    fn visit_mut_ident(&mut self, ident: &mut Ident) {
+        ident.span.ctxt = SyntaxContext::empty();
+    }
+}
+
 pub struct Loader {
     pub debug: bool,
     pub cm: Lrc<SourceMap>,
     pub env: Option<AHashMap<String, String>>,
     pub vars: Option<AHashMap<String, String>>,
     pub typeofs: Option<AHashMap<String, String>>,
+    /// `__APP_VERSION__` and the other build globals, which `vars` can override
+    pub build_defines: AHashMap<String, String>,
+    pub plugins: Vec<Arc<dyn Plugin>>,
+    pub wasm_plugins: Option<Vec<PluginConfig>>,
+    pub loaders: Option<AHashMap<String, ExternalLoader>>,
+    /// Source maps loaders returned, chained into the bundle's source map
+    pub loader_maps: LoaderMaps,
+    pub jsx: JsxOptions,
+    /// Apply react-refresh to project modules
+    pub refresh: bool,
+    /// Building a hot update, which imports from the hmr registry of the page
+    pub hot_update: bool,
+    /// Virtual path the hmr prelude gets loaded from
+    pub hmr_prelude: Option<PathBuf>,
+    /// Define the Node globals modules use
+    pub node_shims: bool,
+    pub external_globals: Option<AHashMap<String, String>>,
+    pub external_modules: Vec<JsWord>,
+    /// The bundle's resolver, to find what imports of modules without side effects point to
+    pub resolver: Arc<dyn Resolve>,
+    pub packages: Arc<Packages>,
+    pub reexports: Reexports,
+    /// `/*#__PURE__*/` style annotations of every module, for the minifier
+    pub comments: SwcComments,
+    /// `/*!`, `@license` and `@preserve` comments of every module
+    pub legal_comments: SwcComments,
 }
 
 impl Load for Loader {
     fn load(&self, f: &FileName) -> std::result::Result<ModuleData, anyhow::Error> {
-        let (fm, is_jsx, is_ts, is_tsx) = match f {
+        let handler =
+            Handler::with_tty_emitter(ColorConfig::Always, false, false, Some(self.cm.clone()));
+
+        // modules which only exist in the bundler
+        let virtual_source = match f {
+            FileName::Custom(name) if name == EMPTY_MODULE => Some("export default {};"),
+            FileName::Custom(name) => stub_source(name),
+            _ => None,
+        };
+
+        if let Some(source) = virtual_source {
+            let fm = self.cm.new_source_file(f.clone(), source.to_owned());
+            let module = parse_file_as_module(
+                &fm,
+                Default::default(),
+                EsVersion::latest(),
+                None,
+                &mut vec![],
+            )
+            .map_err(|_| anyhow!("Failed to parse {f}"))?;
+
+            return Ok(ModuleData {
+                fm,
+                module,
+                helpers: Default::default(),
+            });
+        }
+
+        let (path, fm, is_jsx, is_ts, is_tsx) = match f {
             FileName::Real(path) => {
-                let is_jsx = path
-                    .extension()
-                    .is_some_and(|e| e.to_ascii_lowercase() == "jsx");
-                let is_ts = path
+                let extension = path
                     .extension()
-                    .is_some_and(|e| e.to_ascii_lowercase() == "ts");
-                let is_tsx = path
-                    .extension()
-                    .is_some_and(|e| e.to_ascii_lowercase() == "tsx");
+                    .map(|e| e.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_default();
+                let is_jsx = extension == "jsx";
+                let is_ts = matches!(&*extension, "ts" | "mts" | "cts");
+                let is_tsx = extension == "tsx";
+
+                let mut source = if self.hmr_prelude.as_ref() == Some(path) {
+                    HMR_PRELUDE_SOURCE.to_owned()
+                } else {
+                    fs::read_to_string(path)?
+                };
+                for plugin in &self.plugins {
+                    source = plugin.before_parse(path, source)?;
+                }
+
+                let loader = path.extension().and_then(|e| {
+                    let e = e.to_string_lossy().to_ascii_lowercase();
+                    self.loaders.as_ref()?.get(&e)
+                });
+
+                if let Some(loader) = loader {
+                    // the original source is only kept around to report loader errors against
+                    let original = self
+                        .cm
+                        .new_source_file(FileName::Custom(path.display().to_string()), source);
+                    let output = run_loader(loader, path, &original, &handler)?;
+                    if let Some(map) = &output.map {
+                        self.loader_maps
+                            .lock()
+                            .unwrap()
+                            .insert(path.clone(), map.clone());
+                    }
 
-                (self.cm.load_file(path)?, is_jsx, is_ts, is_tsx)
+                    let fm = self.cm.new_source_file(f.clone(), output.code);
+
+                    (path, fm, false, false, false)
+                } else {
+                    let fm = self.cm.new_source_file(f.clone(), source);
+
+                    (path, fm, is_jsx, is_ts, is_tsx)
+                }
             }
             _ => unreachable!(),
         };
@@ -96,22 +263,76 @@ impl Load for Loader {
             })
         };
 
-        let handler =
-            Handler::with_tty_emitter(ColorConfig::Always, false, false, Some(self.cm.clone()));
+        let comments = SingleThreadedComments::default();
+
+        let mut module = parse_file_as_module(
+            &fm,
+            config,
+            EsVersion::latest(),
+            Some(&comments),
+            &mut vec![],
+        )
+        .map_err(|err| {
+            err.into_diagnostic(&handler).emit();
+            anyhow!("Failed to parse {f}")
+        })?;
 
-        let mut module = parse_file_as_module(&fm, config, EsVersion::latest(), None, &mut vec![])
-            .unwrap_or_else(|err| {
-                err.into_diagnostic(&handler).emit();
-                std::process::exit(0);
+        let mut import_glob = ImportGlob::new(path);
+        module.visit_mut_with(&mut import_glob);
+        if let Some(error) = import_glob.error {
+            return Err(error);
+        }
+
+        for plugin in &self.plugins {
+            module = plugin.after_parse(path, module)?;
+        }
+
+        // wasm plugins and react-refresh work on resolved identifiers
+        let refresh = self.refresh && !is_dependency(path);
+        let resolved = self.wasm_plugins.is_some() || refresh;
+        let unresolved_mark = Mark::new();
+        if resolved {
+            module.visit_mut_with(&mut resolver(unresolved_mark, Mark::new(), is_ts || is_tsx));
+        }
+
+        if let Some(wasm_plugins) = &self.wasm_plugins {
+            module = apply_plugins(
+                wasm_plugins,
+                path,
+                module,
+                &comments,
+                unresolved_mark,
+                self.cm.clone(),
+                self.debug,
+            )?;
+        }
+
+        if refresh {
+            let mut refresh = swc_ecma_transforms_react::refresh(
+                true,
+                Some(Default::default()),
+                self.cm.clone(),
+                Some(&comments),
+                unresolved_mark,
+            );
+            module = module.fold_with(&mut refresh);
+            module.visit_mut_with(&mut RefreshIds {
+                module_id: module_id(&FileName::Real(path.to_owned())),
             });
+        }
+
+        if resolved {
+            clear_marks(&mut module);
+        }
 
         if is_jsx || is_tsx {
-            let mut jsx_folder = swc_ecma_transforms_react::jsx::<SingleThreadedComments>(
-                Default::default(),
-                None,
-                swc_ecma_transforms_react::Options {
-                    development: Some(self.debug),
-                    ..Default::default()
+            // per file `@jsx`, `@jsxFrag`, `@jsxRuntime` and `@jsxImportSource` pragmas are read from the comments
+            let mut jsx_folder = swc_ecma_transforms_react::jsx(
+                self.cm.clone(),
+                Some(&comments),
+                JsxOptions {
+                    development: Some(self.jsx.development.unwrap_or(self.debug)),
+                    ..self.jsx.clone()
                 },
                 Mark::new(),
                 Mark::new(),
@@ -119,6 +340,15 @@ impl Load for Loader {
             module = module.fold_with(&mut jsx_folder);
         }
 
+        // after jsx, which can add imports of the jsx runtime
+        if let Some(globals) = &self.external_globals {
+            let mut external_globals = ExternalGlobals::new(globals);
+            module.visit_mut_with(&mut external_globals);
+            if let Some(error) = external_globals.error {
+                return Err(error);
+            }
+        }
+
         // Do env var stuff
 
         let node_env = if self.debug {
@@ -135,6 +365,8 @@ impl Load for Loader {
             variables.add_envs(envs);
         }
 
+        variables.add_vars(&self.build_defines);
+
         if let Some(var) = &self.vars {
             variables.add_vars(var);
         }
@@ -146,6 +378,85 @@ impl Load for Loader {
         let mut global_pass = variables.build(&handler);
         module = module.fold_with(&mut global_pass);
 
+        // after the globals pass, so inlined `process.env` reads don't count
+        if self.node_shims {
+            let prelude = globals_prelude(&module);
+            if !prelude.is_empty() {
+                let fm = self
+                    .cm
+                    .new_source_file(FileName::Custom(format!("{f} globals")), prelude);
+                let prelude = parse_file_as_module(
+                    &fm,
+                    Default::default(),
+                    EsVersion::latest(),
+                    None,
+                    &mut vec![],
+                )
+                .map_err(|_| anyhow!("Failed to parse the globals for {f}"))?;
+
+                module.body.splice(0..0, prelude.body);
+            }
+        }
+
+        stub_empty_imports(&mut module, f, &*self.resolver);
+
+        SideEffectFreeImports {
+            path,
+            resolver: &*self.resolver,
+            packages: &self.packages,
+            reexports: &self.reexports,
+            external_modules: &self.external_modules,
+        }
+        .apply(&mut module);
+
+        // once the imports are final
+        if refresh {
+            let registry = Registry {
+                base: f,
+                resolver: &*self.resolver,
+                external_modules: &self.external_modules,
+            };
+            if self.hot_update {
+                registry.hot_imports(&mut module)?;
+            } else {
+                registry.register_imports(&mut module);
+            }
+        }
+
+        // source positions are unique across files, so these can all go in one place
+        let (leading, trailing) = comments.borrow_all();
+        for (pos, comments) in leading.iter() {
+            let annotations = comments
+                .iter()
+                .filter(|c| c.kind == CommentKind::Block)
+                .filter(|c| c.text.starts_with("#__") || c.text.starts_with("@__"))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !annotations.is_empty() {
+                self.comments.add_leading_comments(*pos, annotations);
+            }
+
+            let legal = comments
+                .iter()
+                .filter(|c| is_legal(c))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !legal.is_empty() {
+                self.legal_comments.add_leading_comments(*pos, legal);
+            }
+        }
+        for (pos, comments) in trailing.iter() {
+            let legal = comments
+                .iter()
+                .filter(|c| is_legal(c))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !legal.is_empty() {
+                self.legal_comments.add_trailing_comments(*pos, legal);
+            }
+        }
+
         Ok(ModuleData {
             fm,
             module,
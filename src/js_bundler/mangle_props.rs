@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use swc_atoms::JsWord;
+use swc_ecma_ast::{
+    AssignPat, ComputedPropName, Expr, Ident, KeyValuePatProp, KeyValueProp, Lit, MemberProp,
+    Module, ObjectPatProp, Pat, Prop, PropName, Str, SuperProp,
+};
+use swc_ecma_minifier::option::ManglePropertiesOptions;
+use swc_ecma_visit::{noop_visit_mut_type, VisitMut, VisitMutWith};
+
+use super::name_cache::{encode, KEYWORDS};
+use crate::errors::{ApplicationError, Result};
+
+/// Properties which keep their name whatever the options say
+const BUILT_IN: &[&str] = &["__proto__", "constructor", "prototype"];
+
+/// Rename the properties matching `options.regex` in all the modules of a build, so a property
+/// gets the same name in every chunk. Names of `cached` are reused when they're still free.
+/// Returns the renamed properties with their new names
+pub fn mangle_props(
+    modules: &mut [&mut Module],
+    options: &ManglePropertiesOptions,
+    cached: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>> {
+    let Some(regex) = &options.regex else {
+        return Err(ApplicationError::GeneralError(
+            "`mangle.properties` needs a `regex`, like `^_`".into(),
+        ));
+    };
+
+    let mut props = Props {
+        is_mangled: &|name: &str| {
+            regex.is_match(name)
+                && !BUILT_IN.contains(&name)
+                && !options.reserved.iter().any(|r| r == name)
+        },
+        mangled: vec![],
+        kept: Default::default(),
+        names: None,
+    };
+    for module in modules.iter_mut() {
+        module.visit_mut_with(&mut props);
+    }
+
+    // new names don't take a name which is used as it is, or the cached name of another property
+    let mut taken = props.kept.clone();
+    taken.extend(cached.values().map(|name| JsWord::from(name.as_str())));
+
+    let mut names = FxHashMap::default();
+    let mut given = FxHashSet::default();
+    let mut n = 0;
+    for prop in &props.mangled {
+        let cached = cached
+            .get(&**prop)
+            .map(|name| JsWord::from(name.as_str()))
+            .filter(|name| !props.kept.contains(name) && !given.contains(name));
+        let name = cached.unwrap_or_else(|| loop {
+            let name = encode(n);
+            n += 1;
+            if !taken.contains(&name) && !KEYWORDS.contains(&&*name) {
+                break name;
+            }
+        });
+
+        given.insert(name.clone());
+        names.insert(prop.clone(), name);
+    }
+
+    props.names = Some(&names);
+    for module in modules.iter_mut() {
+        module.visit_mut_with(&mut props);
+    }
+
+    Ok(names
+        .into_iter()
+        .map(|(prop, name)| (prop.to_string(), name.to_string()))
+        .collect())
+}
+
+/// Finds property names, then renames them once `names` is set
+struct Props<'a> {
+    is_mangled: &'a dyn Fn(&str) -> bool,
+    /// Properties to rename, in the order they're first used
+    mangled: Vec<JsWord>,
+    /// Properties which keep their name
+    kept: FxHashSet<JsWord>,
+    names: Option<&'a FxHashMap<JsWord, JsWord>>,
+}
+
+impl Props<'_> {
+    /// The new name of a property, when it's renamed
+    fn prop(&mut self, name: &JsWord) -> Option<JsWord> {
+        if let Some(names) = self.names {
+            return names.get(name).cloned();
+        }
+
+        if !(self.is_mangled)(name) {
+            self.kept.insert(name.clone());
+        } else if !self.mangled.contains(name) {
+            self.mangled.push(name.clone());
+        }
+
+        None
+    }
+
+    fn str(&mut self, s: &mut Str) {
+        if let Some(name) = self.prop(&s.value) {
+            s.value = name;
+            s.raw = None;
+        }
+    }
+
+    /// `obj["name"]`
+    fn computed(&mut self, computed: &mut ComputedPropName) {
+        if let Expr::Lit(Lit::Str(s)) = &mut *computed.expr {
+            self.str(s);
+        }
+    }
+}
+
+impl VisitMut for Props<'_> {
+    noop_visit_mut_type!();
+
+    fn visit_mut_member_prop(&mut self, n: &mut MemberProp) {
+        n.visit_mut_children_with(self);
+        match n {
+            MemberProp::Ident(ident) => {
+                if let Some(name) = self.prop(&ident.sym) {
+                    ident.sym = name;
+                }
+            }
+            MemberProp::Computed(computed) => self.computed(computed),
+            MemberProp::PrivateName(_) => {}
+        }
+    }
+
+    fn visit_mut_super_prop(&mut self, n: &mut SuperProp) {
+        n.visit_mut_children_with(self);
+        match n {
+            SuperProp::Ident(ident) => {
+                if let Some(name) = self.prop(&ident.sym) {
+                    ident.sym = name;
+                }
+            }
+            SuperProp::Computed(computed) => self.computed(computed),
+        }
+    }
+
+    /// Keys of object literals, patterns and classes
+    fn visit_mut_prop_name(&mut self, n: &mut PropName) {
+        n.visit_mut_children_with(self);
+        match n {
+            PropName::Ident(ident) => {
+                if let Some(name) = self.prop(&ident.sym) {
+                    ident.sym = name;
+                }
+            }
+            PropName::Str(s) => self.str(s),
+            PropName::Computed(computed) => self.computed(computed),
+            PropName::Num(_) | PropName::BigInt(_) => {}
+        }
+    }
+
+    /// `{ name }` becomes `{ a: name }`
+    fn visit_mut_prop(&mut self, n: &mut Prop) {
+        n.visit_mut_children_with(self);
+        if let Prop::Shorthand(ident) = n {
+            if let Some(name) = self.prop(&ident.sym) {
+                *n = Prop::KeyValue(KeyValueProp {
+                    key: PropName::Ident(Ident::new(name, ident.span)),
+                    value: Box::new(Expr::Ident(ident.clone())),
+                });
+            }
+        }
+    }
+
+    /// `let { name = 1 } = obj` becomes `let { a: name = 1 } = obj`
+    fn visit_mut_object_pat_prop(&mut self, n: &mut ObjectPatProp) {
+        n.visit_mut_children_with(self);
+        if let ObjectPatProp::Assign(assign) = n {
+            if let Some(name) = self.prop(&assign.key.sym) {
+                let binding = Box::new(Pat::Ident(assign.key.clone().into()));
+                let value = match assign.value.take() {
+                    Some(default) => Box::new(Pat::Assign(AssignPat {
+                        span: assign.span,
+                        left: binding,
+                        right: default,
+                        type_ann: None,
+                    })),
+                    None => binding,
+                };
+                *n = ObjectPatProp::KeyValue(KeyValuePatProp {
+                    key: PropName::Ident(Ident::new(name, assign.key.span)),
+                    value,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use swc_cached::regex::CachedRegex;
+    use swc_common::{FileName, SourceMap};
+    use swc_ecma_codegen::{text_writer::JsWriter, Emitter};
+    use swc_ecma_parser::parse_file_as_module;
+
+    use super::*;
+
+    fn options(regex: &str, reserved: &[&str]) -> ManglePropertiesOptions {
+        ManglePropertiesOptions {
+            reserved: reserved.iter().map(|&r| r.into()).collect(),
+            undeclared: None,
+            regex: Some(CachedRegex::new(regex).unwrap()),
+        }
+    }
+
+    /// Mangles `sources` as the modules of one build, and prints them minified
+    fn mangle(
+        sources: &[&str],
+        options: &ManglePropertiesOptions,
+        cached: &BTreeMap<String, String>,
+    ) -> (Vec<String>, BTreeMap<String, String>) {
+        let cm = Arc::new(SourceMap::default());
+        let mut modules = sources
+            .iter()
+            .map(|source| {
+                let fm = cm.new_source_file(FileName::Anon, source.to_string());
+                parse_file_as_module(
+                    &fm,
+                    Default::default(),
+                    Default::default(),
+                    None,
+                    &mut vec![],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let names =
+            mangle_props(&mut modules.iter_mut().collect::<Vec<_>>(), options, cached).unwrap();
+
+        let code = modules
+            .iter()
+            .map(|module| {
+                let mut output = vec![];
+                let mut emitter = Emitter {
+                    cfg: swc_ecma_codegen::Config {
+                        minify: true,
+                        ..Default::default()
+                    },
+                    cm: cm.clone(),
+                    comments: None,
+                    wr: JsWriter::new(cm.clone(), "\n", &mut output, None),
+                };
+                emitter.emit_module(module).unwrap();
+                String::from_utf8(output).unwrap()
+            })
+            .collect();
+
+        (code, names)
+    }
+
+    #[test]
+    fn renames_props_the_same_in_every_module() {
+        let (code, names) = mangle(
+            &[
+                "const o = { _count: 1, _name }; o._count++;",
+                "export class A { _count = 0; get _name() { return this._count; } }",
+            ],
+            &options("^_", &[]),
+            &Default::default(),
+        );
+
+        assert_eq!(
+            names,
+            BTreeMap::from([("_count".into(), "a".into()), ("_name".into(), "b".into())])
+        );
+        assert_eq!(code[0], "const o={a:1,b:_name};o.a++;");
+        assert_eq!(code[1], "export class A{a=0;get b(){return this.a;}}");
+    }
+
+    #[test]
+    fn renames_destructured_props() {
+        let (code, _) = mangle(
+            &["let { _a, _b = 1, _c: c, ...rest } = o; function f({ _a }) {}"],
+            &options("^_", &[]),
+            &Default::default(),
+        );
+
+        assert_eq!(
+            code[0],
+            "let{a:_a,b:_b=1,c:c,...rest}=o;function f({a:_a}){}"
+        );
+    }
+
+    #[test]
+    fn renames_quoted_and_computed_string_keys() {
+        let (code, _) = mangle(
+            &[r#"const o = { "_a": 1, ["_b"]: 2, [key]: 3 }; o["_a"]; o[key]; o["not"];"#],
+            &options("^_", &[]),
+            &Default::default(),
+        );
+
+        assert_eq!(
+            code[0],
+            r#"const o={"a":1,["b"]:2,[key]:3};o["a"];o[key];o["not"];"#
+        );
+    }
+
+    #[test]
+    fn keeps_built_in_and_reserved_props() {
+        let (code, names) = mangle(
+            &["o.__proto__; o.constructor; o.prototype; o._kept; o._renamed;"],
+            &options(".", &["_kept", "o"]),
+            &Default::default(),
+        );
+
+        assert_eq!(names, BTreeMap::from([("_renamed".into(), "a".into())]));
+        assert_eq!(
+            code[0],
+            "o.__proto__;o.constructor;o.prototype;o._kept;o.a;"
+        );
+    }
+
+    #[test]
+    fn new_names_skip_kept_props() {
+        // `a` is used as it is, so `_x` can't take it
+        let (code, _) = mangle(&["o.a; o._x;"], &options("^_", &[]), &Default::default());
+
+        assert_eq!(code[0], "o.a;o.b;");
+    }
+
+    #[test]
+    fn reuses_cached_names_across_builds() {
+        let (_, first) = mangle(
+            &["o._one; o._two;"],
+            &options("^_", &[]),
+            &Default::default(),
+        );
+        assert_eq!(
+            first,
+            BTreeMap::from([("_one".into(), "a".into()), ("_two".into(), "b".into())])
+        );
+
+        // `_one` is gone, `_two` keeps its name and `_three` doesn't take it
+        let (code, second) = mangle(&["o._three; o._two;"], &options("^_", &[]), &first);
+        assert_eq!(code[0], "o.c;o.b;");
+        assert_eq!(
+            second,
+            BTreeMap::from([("_three".into(), "c".into()), ("_two".into(), "b".into())])
+        );
+
+        // a cached name which is now used as it is goes to a new name
+        let (code, _) = mangle(&["o.b; o._two;"], &options("^_", &[]), &first);
+        assert_eq!(code[0], "o.b;o.c;");
+    }
+}
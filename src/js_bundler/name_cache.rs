@@ -0,0 +1,564 @@
+use std::{collections::BTreeMap, fs, path::Path, sync::Mutex};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use swc_atoms::JsWord;
+use swc_common::{sync::Lrc, Mark, SourceMap};
+use swc_ecma_ast::{
+    BindingIdent, Callee, ClassDecl, ClassExpr, Decl, Expr, FnDecl, FnExpr, Id, Ident,
+    ImportSpecifier, Module, ModuleDecl, ModuleItem, Stmt,
+};
+use swc_ecma_minifier::option::{ExtraOptions, MangleOptions, MinifyOptions};
+use swc_ecma_transforms::{hygiene, resolver};
+use swc_ecma_transforms_base::rename::{renamer, Renamer};
+use swc_ecma_visit::{noop_visit_type, Visit, VisitMutWith, VisitWith};
+
+use super::loading::SourcePaths;
+use crate::errors::{ApplicationError, Result};
+
+/// Characters mangled names start with, and the ones they go on with
+const FIRST_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ$_";
+const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ$_0123456789";
+
+/// Words a mangled name can't be
+pub(super) const KEYWORDS: &[&str] = &[
+    "do",
+    "if",
+    "in",
+    "for",
+    "let",
+    "new",
+    "try",
+    "var",
+    "NaN",
+    "case",
+    "else",
+    "enum",
+    "eval",
+    "null",
+    "this",
+    "true",
+    "void",
+    "with",
+    "async",
+    "await",
+    "break",
+    "catch",
+    "class",
+    "const",
+    "false",
+    "super",
+    "throw",
+    "while",
+    "yield",
+    "delete",
+    "export",
+    "import",
+    "public",
+    "return",
+    "static",
+    "switch",
+    "typeof",
+    "default",
+    "extends",
+    "finally",
+    "package",
+    "private",
+    "continue",
+    "debugger",
+    "function",
+    "Infinity",
+    "arguments",
+    "interface",
+    "protected",
+    "undefined",
+    "implements",
+    "instanceof",
+];
+
+/// Mangled names of release builds by module and original name, like terser's `nameCache`. Only
+/// the names of the outermost scope of a bundle (the body of an `iife` bundle's function) are
+/// kept, so unchanged ones get the same name in every build
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct NameCache {
+    /// By the file they're declared in, relative to the project root, then by name. A name
+    /// declared more than once in a file gets `#2`, `#3`, ... appended in source order
+    #[serde(default)]
+    pub vars: BTreeMap<String, BTreeMap<String, String>>,
+    /// Mangled properties, see `MangleOptions::props`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub props: BTreeMap<String, String>,
+}
+
+impl NameCache {
+    /// An empty cache when the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json)
+            .map_err(|e| ApplicationError::GeneralError(format!("{}: {e}", path.display()).into()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| ApplicationError::GeneralError(e.into()))?;
+        fs::write(path, json + "\n")?;
+        Ok(())
+    }
+
+    /// Mangle the names of a module compressed by the minifier. Outermost names get their cached
+    /// name if they can, and the names they were given are added to `next`. Names in nested scopes
+    /// are left to the minifier's mangler
+    pub fn mangle(
+        &self,
+        mut module: Module,
+        options: &MangleOptions,
+        cm: &Lrc<SourceMap>,
+        paths: &SourcePaths,
+        next: &mut NameCache,
+    ) -> Module {
+        let outermost = outermost_keys(&module, cm, paths);
+        let preserved = preserved_ids(&module, options);
+
+        // new names don't take the cached name of another one
+        let cached = outermost
+            .iter()
+            .filter_map(|(id, key)| Some((id.clone(), self.get(key)?)))
+            .collect::<FxHashMap<_, _>>();
+        let reserved = cached.values().cloned().collect();
+
+        let given = Mutex::default();
+        module.visit_mut_with(&mut renamer(
+            hygiene::Config {
+                keep_class_names: options.keep_class_names,
+                safari_10: options.safari10,
+                top_level_mark: Mark::new(),
+            },
+            CachedRenamer {
+                outermost: outermost.iter().map(|(id, _)| id.clone()).collect(),
+                cached,
+                reserved: Mutex::new(reserved),
+                preserved,
+                tried: Default::default(),
+                given: &given,
+            },
+        ));
+
+        let given = given.into_inner().unwrap();
+        for (id, (file, name)) in &outermost {
+            if let Some(given) = given.get(id) {
+                next.vars
+                    .entry(file.clone())
+                    .or_default()
+                    .entry(name.clone())
+                    .or_insert_with(|| given.to_string());
+            }
+        }
+
+        // then the minifier mangles everything else, keeping the names the outermost ones were
+        // given. The renamer leaves names to tell bindings apart, so scopes are resolved again
+        let unresolved_mark = Mark::new();
+        let top_level_mark = Mark::new();
+        module.visit_mut_with(&mut resolver(unresolved_mark, top_level_mark, false));
+
+        let mut options = options.clone();
+        options.reserved.extend(
+            outermost
+                .iter()
+                .filter_map(|(id, _)| given.get(id).cloned()),
+        );
+        let minify = MinifyOptions {
+            mangle: Some(options),
+            ..Default::default()
+        };
+        swc_ecma_minifier::optimize(
+            module.into(),
+            cm.clone(),
+            None,
+            None,
+            &minify,
+            &ExtraOptions {
+                unresolved_mark,
+                top_level_mark,
+            },
+        )
+        .expect_module()
+    }
+
+    fn get(&self, (file, name): &(String, String)) -> Option<JsWord> {
+        let name = self.vars.get(file)?.get(name)?;
+        Some(JsWord::from(name.as_str()))
+    }
+}
+
+/// Gives outermost names their cached name, and every other one a temporary short name, which
+/// the minifier's mangler replaces afterwards. Outermost names are renamed first, as the renamer
+/// goes from the outer scopes in
+struct CachedRenamer<'a> {
+    outermost: FxHashSet<Id>,
+    cached: FxHashMap<Id, JsWord>,
+    /// Names new ones can't take: the cached ones, and those of outermost names for nested ones,
+    /// so that only outermost names have them
+    reserved: Mutex<FxHashSet<JsWord>>,
+    preserved: FxHashSet<Id>,
+    /// Outermost names which were offered their cached name already
+    tried: Mutex<FxHashSet<Id>>,
+    /// The last name each name was offered, which is the one it got
+    given: &'a Mutex<FxHashMap<Id, JsWord>>,
+}
+
+impl Renamer for CachedRenamer<'_> {
+    const MANGLE: bool = true;
+    const RESET_N: bool = false;
+
+    fn preserved_ids_for_module(&mut self, _: &Module) -> FxHashSet<Id> {
+        self.preserved.clone()
+    }
+
+    fn new_name_for(&self, orig: &Id, n: &mut usize) -> JsWord {
+        // the cached name is tried first, and a new one if it's taken in this scope
+        let name = match self.cached.get(orig) {
+            Some(cached) if self.tried.lock().unwrap().insert(orig.clone()) => cached.clone(),
+            _ => loop {
+                let name = encode(*n);
+                *n += 1;
+                if !self.reserved.lock().unwrap().contains(&name) && !KEYWORDS.contains(&&*name) {
+                    break name;
+                }
+            },
+        };
+
+        if self.outermost.contains(orig) {
+            self.reserved.lock().unwrap().insert(name.clone());
+        }
+
+        self.given
+            .lock()
+            .unwrap()
+            .insert(orig.clone(), name.clone());
+
+        name
+    }
+}
+
+/// The `n`th short name: `a` to `_`, then `aa`, `ba`, ...
+pub(super) fn encode(mut n: usize) -> JsWord {
+    let mut name = String::new();
+    name.push(FIRST_CHARS[n % FIRST_CHARS.len()] as char);
+    n /= FIRST_CHARS.len();
+
+    while n > 0 {
+        n -= 1;
+        name.push(CHARS[n % CHARS.len()] as char);
+        n /= CHARS.len();
+    }
+
+    name.into()
+}
+
+/// Names declared at the top level of a module, or of the function an `iife` bundle is wrapped in,
+/// in source order, with the file and name they're cached by
+fn outermost_keys(
+    module: &Module,
+    cm: &SourceMap,
+    paths: &SourcePaths,
+) -> Vec<(Id, (String, String))> {
+    let mut seen = FxHashSet::default();
+    let mut counts = FxHashMap::<(String, JsWord), usize>::default();
+
+    let mut keys = vec![];
+    for ident in outermost_idents(module) {
+        if !seen.insert(ident.to_id()) {
+            continue;
+        }
+
+        // names the bundler made up have no file
+        let file = match ident.span.is_dummy() {
+            true => String::new(),
+            false => paths.name(&cm.span_to_filename(ident.span)),
+        };
+        let count = counts.entry((file.clone(), ident.sym.clone())).or_default();
+        *count += 1;
+        let name = match *count {
+            1 => ident.sym.to_string(),
+            n => format!("{}#{n}", ident.sym),
+        };
+        keys.push((ident.to_id(), (file, name)));
+    }
+
+    keys
+}
+
+fn outermost_idents(module: &Module) -> Vec<Ident> {
+    let mut idents = vec![];
+    for item in &module.body {
+        match item {
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                idents.extend(import.specifiers.iter().map(|s| match s {
+                    ImportSpecifier::Named(s) => s.local.clone(),
+                    ImportSpecifier::Default(s) => s.local.clone(),
+                    ImportSpecifier::Namespace(s) => s.local.clone(),
+                }));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                declared_idents(&export.decl, &mut idents)
+            }
+            ModuleItem::Stmt(Stmt::Decl(decl)) => declared_idents(decl, &mut idents),
+            ModuleItem::Stmt(Stmt::Expr(expr)) => {
+                let Some(body) = iife_body(&expr.expr) else {
+                    continue;
+                };
+                for stmt in body {
+                    if let Stmt::Decl(decl) = stmt {
+                        declared_idents(decl, &mut idents);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    idents
+}
+
+/// Statements of `(function() { ... })()`, or the `!function() { ... }()` the minifier turns it into
+fn iife_body(expr: &Expr) -> Option<&[Stmt]> {
+    match expr {
+        Expr::Paren(paren) => iife_body(&paren.expr),
+        Expr::Unary(unary) => iife_body(&unary.arg),
+        Expr::Call(call) => {
+            let Callee::Expr(callee) = &call.callee else {
+                return None;
+            };
+            match &**callee {
+                Expr::Paren(paren) => match &*paren.expr {
+                    Expr::Fn(f) => Some(&f.function.body.as_ref()?.stmts),
+                    _ => None,
+                },
+                Expr::Fn(f) => Some(&f.function.body.as_ref()?.stmts),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn declared_idents(decl: &Decl, idents: &mut Vec<Ident>) {
+    match decl {
+        Decl::Fn(f) => idents.push(f.ident.clone()),
+        Decl::Class(c) => idents.push(c.ident.clone()),
+        Decl::Var(var) => {
+            let mut bindings = BindingIdents::default();
+            for declarator in &var.decls {
+                declarator.name.visit_with(&mut bindings);
+            }
+            idents.extend(bindings.0);
+        }
+        _ => {}
+    }
+}
+
+/// Names a pattern declares, leaving out anything in default values
+#[derive(Default)]
+struct BindingIdents(Vec<Ident>);
+
+impl Visit for BindingIdents {
+    noop_visit_type!();
+
+    fn visit_binding_ident(&mut self, n: &BindingIdent) {
+        self.0.push(n.id.clone());
+    }
+
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+/// Names the mangle options keep: reserved ones, class and function names, and the module's
+/// top level names unless `top_level` is set
+fn preserved_ids(module: &Module, options: &MangleOptions) -> FxHashSet<Id> {
+    let mut preserver = Preserver {
+        options,
+        preserved: Default::default(),
+    };
+    module.visit_with(&mut preserver);
+
+    if !options.top_level.unwrap_or_default() {
+        for item in &module.body {
+            let decl = match item {
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => &export.decl,
+                ModuleItem::Stmt(Stmt::Decl(decl)) => decl,
+                _ => continue,
+            };
+            let mut idents = vec![];
+            declared_idents(decl, &mut idents);
+            preserver.preserved.extend(idents.iter().map(Ident::to_id));
+        }
+    }
+
+    preserver.preserved
+}
+
+struct Preserver<'a> {
+    options: &'a MangleOptions,
+    preserved: FxHashSet<Id>,
+}
+
+impl Visit for Preserver<'_> {
+    noop_visit_type!();
+
+    fn visit_binding_ident(&mut self, n: &BindingIdent) {
+        if self.options.reserved.contains(&n.id.sym) {
+            self.preserved.insert(n.id.to_id());
+        }
+    }
+
+    fn visit_fn_decl(&mut self, n: &FnDecl) {
+        n.visit_children_with(self);
+        if self.options.keep_fn_names || self.options.reserved.contains(&n.ident.sym) {
+            self.preserved.insert(n.ident.to_id());
+        }
+    }
+
+    fn visit_fn_expr(&mut self, n: &FnExpr) {
+        n.visit_children_with(self);
+        if let Some(ident) = &n.ident {
+            if self.options.keep_fn_names || self.options.reserved.contains(&ident.sym) {
+                self.preserved.insert(ident.to_id());
+            }
+        }
+    }
+
+    fn visit_class_decl(&mut self, n: &ClassDecl) {
+        n.visit_children_with(self);
+        if self.options.keep_class_names || self.options.reserved.contains(&n.ident.sym) {
+            self.preserved.insert(n.ident.to_id());
+        }
+    }
+
+    fn visit_class_expr(&mut self, n: &ClassExpr) {
+        n.visit_children_with(self);
+        if let Some(ident) = &n.ident {
+            if self.options.keep_class_names || self.options.reserved.contains(&ident.sym) {
+                self.preserved.insert(ident.to_id());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swc_common::{FileName, Globals, GLOBALS};
+    use swc_ecma_codegen::{text_writer::JsWriter, Emitter};
+    use swc_ecma_parser::parse_file_as_module;
+
+    use super::*;
+
+    const FILES: &[(&str, &str)] = &[
+        (
+            "/project/src/a.js",
+            "const value = 1; const twice = (only) => only * 2; function read(first, second) { const local = value; return local + first * second; } console.log(twice(read(2, 3)));",
+        ),
+        (
+            "/project/src/b.js",
+            "const value = 2; function read() { const other = value; return other * 2; } console.log(read());",
+        ),
+    ];
+
+    /// Mangles the files as one bundle, like the bundler leaves them: side by side, with their
+    /// top level names clashing
+    fn build(order: &[usize], cache: &NameCache) -> (String, NameCache) {
+        let cm = Lrc::<SourceMap>::default();
+        GLOBALS.set(&Globals::new(), || {
+            let mut body = vec![];
+            for &i in order {
+                let (path, source) = FILES[i];
+                let fm = cm.new_source_file(FileName::Real(path.into()), source.to_owned());
+                let mut module = parse_file_as_module(
+                    &fm,
+                    Default::default(),
+                    Default::default(),
+                    None,
+                    &mut vec![],
+                )
+                .unwrap();
+                module.visit_mut_with(&mut resolver(Mark::new(), Mark::new(), false));
+                body.extend(module.body);
+            }
+            let module = Module {
+                span: Default::default(),
+                body,
+                shebang: None,
+            };
+
+            let options = MangleOptions {
+                top_level: Some(true),
+                ..Default::default()
+            };
+            let paths = SourcePaths::new(Path::new("/project"));
+            let mut next = NameCache::default();
+            let module = cache.mangle(module, &options, &cm, &paths, &mut next);
+
+            let mut output = vec![];
+            let mut emitter = Emitter {
+                cfg: Default::default(),
+                cm: cm.clone(),
+                comments: None,
+                wr: JsWriter::new(cm.clone(), "\n", &mut output, None),
+            };
+            emitter.emit_module(&module).unwrap();
+            (String::from_utf8(output).unwrap(), next)
+        })
+    }
+
+    #[test]
+    fn names_are_cached_by_file() {
+        let (code, cache) = build(&[0, 1], &NameCache::default());
+        assert!(!code.contains("value") && !code.contains("local") && !code.contains("other"));
+
+        // nested names are mangled apart from each other
+        let params = code.split_once("function ").unwrap().1;
+        let params = &params[params.find('(').unwrap() + 1..params.find(')').unwrap()];
+        let (first, second) = params.split_once(", ").unwrap();
+        assert_ne!(first, second);
+
+        let names = cache
+            .vars
+            .iter()
+            .map(|(file, names)| (file.as_str(), names.keys().map(String::as_str).collect()))
+            .collect::<Vec<(_, Vec<_>)>>();
+        assert_eq!(
+            names,
+            [
+                ("src/a.js", vec!["read", "twice", "value"]),
+                ("src/b.js", vec!["read", "value"])
+            ]
+        );
+        let given = cache
+            .vars
+            .values()
+            .flat_map(|names| names.values())
+            .collect::<FxHashSet<_>>();
+        assert_eq!(given.len(), 5);
+    }
+
+    #[test]
+    fn builds_with_the_cache_are_the_same() {
+        let (code, cache) = build(&[0, 1], &NameCache::default());
+
+        let (again, next) = build(&[0, 1], &cache);
+        assert_eq!(again, code);
+        assert_eq!(next, cache);
+
+        // the files' names don't depend on which one the bundler put first
+        let (_, next) = build(&[1, 0], &cache);
+        assert_eq!(next, cache);
+    }
+
+    #[test]
+    fn encodes_short_names() {
+        let names = [0, 1, 53, 54, 55, 108, 3509, 3510].map(|n| encode(n).to_string());
+        assert_eq!(names, ["a", "b", "_", "aa", "ba", "ab", "_9", "aaa"]);
+    }
+}
@@ -0,0 +1,595 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Error};
+use glob::Pattern;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use swc_common::{FileName, DUMMY_SP};
+use swc_ecma_ast::{
+    Decl, Expr, Ident, ImportSpecifier, Lit, Module, ModuleDecl, ModuleItem, Pat, Stmt, UnaryExpr,
+    UnaryOp, VarDecl, VarDeclKind, VarDeclarator,
+};
+use swc_ecma_loader::resolve::Resolve;
+
+/// Modules stubbed out with `false` in a `browser` field resolve to this, and get loaded as an empty module
+pub const EMPTY_MODULE: &str = "<empty module>";
+
+/// Named imports of modules resolving to `EMPTY_MODULE` become `undefined`, as the empty module
+/// only has a `default` export: `import { readFile } from "fs"` to `var readFile = void 0`
+pub fn stub_empty_imports(module: &mut Module, base: &FileName, resolver: &dyn Resolve) {
+    let is_empty = |src: &str| matches!(resolver.resolve(base, src), Ok(FileName::Custom(name)) if name == EMPTY_MODULE);
+
+    let mut body = Vec::with_capacity(module.body.len());
+    for item in module.body.drain(..) {
+        let ModuleItem::ModuleDecl(ModuleDecl::Import(mut import)) = item else {
+            body.push(item);
+            continue;
+        };
+        if import.type_only || !is_empty(&import.src.value) {
+            body.push(ModuleItem::ModuleDecl(ModuleDecl::Import(import)));
+            continue;
+        }
+
+        let (named, others): (Vec<_>, Vec<_>) = import
+            .specifiers
+            .drain(..)
+            .partition(|s| matches!(s, ImportSpecifier::Named(s) if !s.is_type_only));
+        let had_specifiers = !named.is_empty() || !others.is_empty();
+
+        // `import "fs"` and default or namespace imports still load it
+        if !others.is_empty() || !had_specifiers {
+            import.specifiers = others;
+            body.push(ModuleItem::ModuleDecl(ModuleDecl::Import(import)));
+        }
+        for specifier in named {
+            let ImportSpecifier::Named(specifier) = specifier else {
+                continue;
+            };
+            body.push(undefined(specifier.local));
+        }
+    }
+    module.body = body;
+}
+
+/// `var local = void 0`
+fn undefined(local: Ident) -> ModuleItem {
+    let void = Expr::Unary(UnaryExpr {
+        span: DUMMY_SP,
+        op: UnaryOp::Void,
+        arg: Box::new(Expr::Lit(Lit::Num(0.0.into()))),
+    });
+
+    ModuleItem::Stmt(Stmt::Decl(Decl::Var(Box::new(VarDecl {
+        span: DUMMY_SP,
+        kind: VarDeclKind::Var,
+        declare: false,
+        decls: vec![VarDeclarator {
+            span: DUMMY_SP,
+            name: Pat::Ident(local.into()),
+            init: Some(Box::new(void)),
+            definite: false,
+        }],
+    }))))
+}
+
+/// The parts of `package.json` the bundler resolves with
+#[derive(Deserialize, Debug, Default)]
+struct PackageJson {
+    name: Option<String>,
+    version: Option<String>,
+    /// A SPDX expression, or the deprecated `{ "type" }` object
+    license: Option<Value>,
+    exports: Option<Value>,
+    imports: Option<Value>,
+    browser: Option<Value>,
+    #[serde(rename = "sideEffects")]
+    side_effects: Option<Value>,
+}
+
+/// Parsed `package.json` files by directory, shared between the resolver and the loader
+#[derive(Default)]
+pub struct Packages {
+    /// `None` if there is none
+    cache: Mutex<FxHashMap<PathBuf, Option<Arc<PackageJson>>>>,
+}
+
+impl Packages {
+    fn package_json(&self, dir: &Path) -> Option<Arc<PackageJson>> {
+        let mut cache = self.cache.lock().unwrap();
+        cache
+            .entry(dir.to_owned())
+            .or_insert_with(|| {
+                let json = fs::read_to_string(dir.join("package.json")).ok()?;
+                serde_json::from_str(&json).ok().map(Arc::new)
+            })
+            .clone()
+    }
+
+    /// The package a file belongs to
+    fn package_root(&self, file: &Path) -> Option<(PathBuf, Arc<PackageJson>)> {
+        file.parent()?
+            .ancestors()
+            .find_map(|dir| Some((dir.to_owned(), self.package_json(dir)?)))
+    }
+
+    /// Whether loading a file can have side effects, going by the `sideEffects` field of its package.
+    /// Files outside of a package, or in one without the field, are assumed to have them
+    pub fn has_side_effects(&self, file: &Path) -> bool {
+        let Some((dir, package)) = self.package_root(file) else {
+            return true;
+        };
+        let Ok(relative) = file.strip_prefix(&dir) else {
+            return true;
+        };
+
+        // patterns without a `/` match the file name in any directory, like in webpack
+        let matches = |pattern: &str| {
+            let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+            let path = if pattern.contains('/') {
+                relative
+            } else {
+                Path::new(relative.file_name().unwrap_or_default())
+            };
+            Pattern::new(pattern).is_ok_and(|p| p.matches_path(path))
+        };
+
+        match &package.side_effects {
+            Some(Value::Bool(side_effects)) => *side_effects,
+            Some(Value::String(pattern)) => matches(pattern),
+            Some(Value::Array(patterns)) => patterns.iter().filter_map(Value::as_str).any(matches),
+            _ => true,
+        }
+    }
+
+    /// `name@version (license)` of the npm package a file belongs to, for license notices.
+    /// `None` for files which aren't in `node_modules`
+    pub fn notice(&self, file: &Path) -> Option<String> {
+        let (dir, package) = self.package_root(file)?;
+        if !dir.components().any(|c| c.as_os_str() == "node_modules") {
+            return None;
+        }
+
+        let name = package
+            .name
+            .clone()
+            .or_else(|| Some(dir.file_name()?.to_string_lossy().into_owned()))?;
+        let version = package.version.as_deref().unwrap_or("unknown");
+        let license = match &package.license {
+            Some(Value::String(license)) => license.as_str(),
+            Some(Value::Object(license)) => license
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("UNKNOWN"),
+            _ => "UNKNOWN",
+        };
+
+        Some(format!("{name}@{version} ({license})"))
+    }
+}
+
+/// What a `browser` field maps a module or file to
+enum Replacement {
+    Empty,
+    Module(String),
+}
+
+/// Applies `package.json` `exports`, `imports` and `browser` fields on top of `inner`
+pub struct PackageResolver<R> {
+    inner: R,
+    /// `exports`/`imports` conditions which are matched, besides `default`
+    conditions: Vec<String>,
+    packages: Arc<Packages>,
+    preserve_symlinks: bool,
+    /// Real paths of resolved files, and the path they were first resolved as
+    identities: Mutex<FxHashMap<PathBuf, PathBuf>>,
+}
+
+impl<R: Resolve> PackageResolver<R> {
+    pub fn new(
+        inner: R,
+        packages: Arc<Packages>,
+        debug: bool,
+        conditions: Option<Vec<String>>,
+        preserve_symlinks: bool,
+    ) -> Self {
+        let mode = if debug { "development" } else { "production" };
+        let mut default_conditions = ["browser", "module", "import", mode]
+            .map(String::from)
+            .to_vec();
+        default_conditions.extend(conditions.unwrap_or_default());
+
+        Self {
+            inner,
+            conditions: default_conditions,
+            packages,
+            preserve_symlinks,
+            identities: Default::default(),
+        }
+    }
+
+    /// Look up a package by name in the `node_modules` directories above `file`
+    fn find_package(&self, file: &Path, name: &str) -> Option<(PathBuf, Arc<PackageJson>)> {
+        file.parent()?.ancestors().find_map(|dir| {
+            let dir = dir.join("node_modules").join(name);
+            let package = self.packages.package_json(&dir)?;
+            Some((dir, package))
+        })
+    }
+
+    /// Pick the targets of an `exports`/`imports` value which match our conditions.
+    /// `None` if nothing matched, and an empty list if the path is explicitly excluded with `null`
+    fn targets(&self, target: &Value, matched: &str) -> Option<Vec<String>> {
+        match target {
+            Value::String(target) => Some(vec![target.replace('*', matched)]),
+            Value::Array(targets) => {
+                let targets = targets
+                    .iter()
+                    .filter_map(|t| self.targets(t, matched))
+                    .flatten()
+                    .collect::<Vec<_>>();
+                (!targets.is_empty()).then_some(targets)
+            }
+            // conditions are matched in the order they're listed in
+            Value::Object(conditions) => conditions.iter().find_map(|(condition, target)| {
+                if condition == "default" || self.conditions.contains(condition) {
+                    self.targets(target, matched)
+                } else {
+                    None
+                }
+            }),
+            Value::Null => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    /// Resolve `./subpath` (or `.`) through a package's `exports`
+    fn resolve_exports(
+        &self,
+        dir: &Path,
+        exports: &Value,
+        subpath: &str,
+    ) -> Result<PathBuf, Error> {
+        let targets = match exports {
+            Value::Object(map) if map.keys().all(|k| k.starts_with('.')) => {
+                match_key(map, subpath).and_then(|(target, matched)| self.targets(target, matched))
+            }
+            // sugar for `{ ".": exports }`
+            _ if subpath == "." => self.targets(exports, ""),
+            _ => None,
+        };
+
+        targets
+            .into_iter()
+            .flatten()
+            .filter(|t| t.starts_with("./"))
+            .map(|t| dir.join(t))
+            .find(|p| p.is_file())
+            .ok_or_else(|| {
+                anyhow!(
+                    "`{subpath}` is not exported for the conditions {:?} by {}",
+                    self.conditions,
+                    dir.join("package.json").display()
+                )
+            })
+    }
+
+    /// Resolve a `#specifier` through the `imports` of the package `base` is in
+    fn resolve_imports(&self, base: &Path, specifier: &str) -> Result<FileName, Error> {
+        let (dir, package) = self
+            .packages
+            .package_root(base)
+            .ok_or_else(|| anyhow!("`{specifier}` used outside of a package"))?;
+
+        let targets = match &package.imports {
+            Some(Value::Object(imports)) => match_key(imports, specifier)
+                .and_then(|(target, matched)| self.targets(target, matched)),
+            _ => None,
+        };
+
+        for target in targets.into_iter().flatten() {
+            // targets are either files in the package, or other packages
+            let result = if target.starts_with("./") {
+                Ok(self.finish(dir.join(target)))
+            } else {
+                self.resolve(&FileName::Real(dir.join("package.json")), &target)
+            };
+
+            if result.is_ok() {
+                return result;
+            }
+        }
+
+        Err(anyhow!(
+            "`{specifier}` is not defined by the imports of {}",
+            dir.join("package.json").display()
+        ))
+    }
+
+    /// Give a resolved file a single identity, however it was reached, and apply
+    /// the `browser` field of the package it's in
+    fn finish(&self, file: PathBuf) -> FileName {
+        let file = match fs::canonicalize(&file) {
+            // the first path a file was resolved as is kept, so symlinks don't bundle it twice
+            Ok(real) if self.preserve_symlinks => self
+                .identities
+                .lock()
+                .unwrap()
+                .entry(real)
+                .or_insert(file)
+                .clone(),
+            Ok(real) => real,
+            Err(_) => file,
+        };
+
+        let Some((dir, package)) = self.packages.package_root(&file) else {
+            return FileName::Real(file);
+        };
+        let Ok(relative) = file.strip_prefix(&dir) else {
+            return FileName::Real(file);
+        };
+
+        // keys may leave out the extension
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let without_extension = relative
+            .rsplit_once('.')
+            .map_or(&*relative, |(stem, _)| stem);
+
+        let replacement = browser_replacement(&package, |key| {
+            let key = key.strip_prefix("./").unwrap_or(key);
+            key == relative || key == without_extension
+        });
+
+        match replacement {
+            Some(Replacement::Empty) => FileName::Custom(EMPTY_MODULE.to_owned()),
+            Some(Replacement::Module(target)) => self
+                .inner
+                .resolve(&FileName::Real(dir.join("package.json")), &target)
+                .unwrap_or(FileName::Real(file)),
+            None => FileName::Real(file),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for PackageResolver<R> {
+    fn resolve(&self, base: &FileName, module_specifier: &str) -> Result<FileName, Error> {
+        let FileName::Real(base_path) = base else {
+            return self.inner.resolve(base, module_specifier);
+        };
+
+        if module_specifier.starts_with('#') {
+            return self.resolve_imports(base_path, module_specifier);
+        }
+
+        let is_bare =
+            !module_specifier.starts_with('.') && !Path::new(module_specifier).is_absolute();
+        if is_bare {
+            // the importing package can swap out, or stub out, the modules it imports
+            if let Some((dir, package)) = self.packages.package_root(base_path) {
+                match browser_replacement(&package, |key| key == module_specifier) {
+                    Some(Replacement::Empty) => {
+                        return Ok(FileName::Custom(EMPTY_MODULE.to_owned()))
+                    }
+                    Some(Replacement::Module(target)) if target != module_specifier => {
+                        return self.resolve(&FileName::Real(dir.join("package.json")), &target);
+                    }
+                    _ => {}
+                }
+            }
+
+            let (name, subpath) = split_specifier(module_specifier);
+            if let Some((dir, package)) = self.find_package(base_path, name) {
+                if let Some(exports) = &package.exports {
+                    let file = self.resolve_exports(&dir, exports, &subpath)?;
+                    return Ok(self.finish(file));
+                }
+            }
+        }
+
+        match self.inner.resolve(base, module_specifier)? {
+            FileName::Real(file) => Ok(self.finish(file)),
+            // the inner resolver does its own `browser` field handling, and only uses this for `false`
+            FileName::Custom(_) => Ok(FileName::Custom(EMPTY_MODULE.to_owned())),
+            file => Ok(file),
+        }
+    }
+}
+
+/// Split a bare specifier into the package name, and a subpath for `exports`
+fn split_specifier(specifier: &str) -> (&str, String) {
+    let name_len = if specifier.starts_with('@') {
+        specifier.match_indices('/').nth(1).map(|(i, _)| i)
+    } else {
+        specifier.find('/')
+    }
+    .unwrap_or(specifier.len());
+
+    let (name, rest) = specifier.split_at(name_len);
+    (name, format!(".{rest}"))
+}
+
+/// Find the `exports`/`imports` entry for a key. Exact keys win, then the pattern
+/// with the longest prefix before its `*`. Returns what the `*` matched
+fn match_key<'a, 'b>(map: &'a Map<String, Value>, key: &'b str) -> Option<(&'a Value, &'b str)> {
+    if let Some(target) = map.get(key).filter(|_| !key.contains('*')) {
+        return Some((target, ""));
+    }
+
+    map.iter()
+        .filter_map(|(pattern, target)| {
+            let (prefix, suffix) = pattern.split_once('*')?;
+            let matched = key.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some((prefix.len(), pattern.len(), target, matched))
+        })
+        .max_by_key(|(prefix_len, pattern_len, _, _)| (*prefix_len, *pattern_len))
+        .map(|(_, _, target, matched)| (target, matched))
+}
+
+fn browser_replacement(
+    package: &PackageJson,
+    matches: impl Fn(&str) -> bool,
+) -> Option<Replacement> {
+    let Some(Value::Object(browser)) = &package.browser else {
+        return None;
+    };
+
+    browser
+        .iter()
+        .find(|(key, _)| matches(key))
+        .and_then(|(_, value)| match value {
+            Value::Bool(false) => Some(Replacement::Empty),
+            Value::String(target) => Some(Replacement::Module(target.clone())),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn splits_specifiers_into_package_and_subpath() {
+        assert_eq!(split_specifier("react"), ("react", ".".to_owned()));
+        assert_eq!(
+            split_specifier("react-dom/client"),
+            ("react-dom", "./client".to_owned())
+        );
+        assert_eq!(
+            split_specifier("@scope/pkg"),
+            ("@scope/pkg", ".".to_owned())
+        );
+        assert_eq!(
+            split_specifier("@scope/pkg/a/b.js"),
+            ("@scope/pkg", "./a/b.js".to_owned())
+        );
+    }
+
+    #[test]
+    fn exact_keys_win_over_patterns() {
+        let exports = json!({
+            "./*": "./dist/*.js",
+            "./feature": "./feature.js",
+        });
+        let map = exports.as_object().unwrap();
+
+        assert_eq!(
+            match_key(map, "./feature"),
+            Some((&json!("./feature.js"), ""))
+        );
+        assert_eq!(
+            match_key(map, "./other"),
+            Some((&json!("./dist/*.js"), "other"))
+        );
+    }
+
+    #[test]
+    fn longest_pattern_prefix_wins() {
+        let exports = json!({
+            "./*": "./dist/*.js",
+            "./features/*": "./src/features/*.js",
+            "./features/*.css": "./styles/*.css",
+            "./internal/*": null,
+        });
+        let map = exports.as_object().unwrap();
+
+        assert_eq!(
+            match_key(map, "./features/a/b"),
+            Some((&json!("./src/features/*.js"), "a/b"))
+        );
+        assert_eq!(
+            match_key(map, "./features/theme.css"),
+            Some((&json!("./styles/*.css"), "theme"))
+        );
+        assert_eq!(match_key(map, "./internal/x"), Some((&Value::Null, "x")));
+        assert_eq!(match_key(map, "other"), None);
+    }
+
+    #[test]
+    fn browser_field_replaces_and_stubs_modules() {
+        let package: PackageJson = serde_json::from_value(json!({
+            "browser": { "fs": false, "./node.js": "./browser.js", "other": true },
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            browser_replacement(&package, |key| key == "fs"),
+            Some(Replacement::Empty)
+        ));
+        assert!(matches!(
+            browser_replacement(&package, |key| key == "./node.js"),
+            Some(Replacement::Module(target)) if target == "./browser.js"
+        ));
+        assert!(browser_replacement(&package, |key| key == "other").is_none());
+        assert!(browser_replacement(&package, |key| key == "path").is_none());
+    }
+
+    /// Stubs out `fs`, and resolves everything else next to the importer
+    struct StubResolver;
+
+    impl Resolve for StubResolver {
+        fn resolve(&self, _: &FileName, src: &str) -> Result<FileName, Error> {
+            match src {
+                "fs" => Ok(FileName::Custom(EMPTY_MODULE.to_owned())),
+                src => Ok(FileName::Real(Path::new("/project/src").join(src))),
+            }
+        }
+    }
+
+    #[test]
+    fn named_imports_of_empty_modules_are_undefined() {
+        let source = r#"
+            import "fs";
+            import fs, { readFile, writeFile as write } from "fs";
+            import * as ns from "fs";
+            import { named } from "./real.js";
+        "#;
+        let cm = swc_common::SourceMap::default();
+        let fm = cm.new_source_file(FileName::Anon, source.to_owned());
+        let mut module = swc_ecma_parser::parse_file_as_module(
+            &fm,
+            Default::default(),
+            Default::default(),
+            None,
+            &mut vec![],
+        )
+        .unwrap();
+
+        stub_empty_imports(
+            &mut module,
+            &FileName::Real("/project/src/app.js".into()),
+            &StubResolver,
+        );
+
+        let items = module
+            .body
+            .iter()
+            .map(|item| match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                    format!("import {} {}", import.specifiers.len(), import.src.value)
+                }
+                ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) => match &var.decls[0].name {
+                    Pat::Ident(local) => format!("var {}", local.id.sym),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                "import 0 fs",
+                "import 1 fs",
+                "var readFile",
+                "var write",
+                "import 1 fs",
+                "import 1 ./real.js",
+            ]
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use swc_ecma_ast::Module;
+
+/// Custom transforms which run on every module in the bundle
+///
+/// Every hook has a default implementation which passes its input through untouched,
+/// so only the needed ones have to be implemented.
+pub trait Plugin: Send + Sync {
+    /// Runs on the raw source text of a file, before it gets parsed
+    fn before_parse(&self, _path: &Path, source: String) -> anyhow::Result<String> {
+        Ok(source)
+    }
+
+    /// Runs on a freshly parsed module, before the jsx and global variable transforms
+    fn after_parse(&self, _path: &Path, module: Module) -> anyhow::Result<Module> {
+        Ok(module)
+    }
+
+    /// Runs on each bundled module, before it gets minified
+    fn after_bundle(&self, module: Module) -> anyhow::Result<Module> {
+        Ok(module)
+    }
+}
@@ -0,0 +1,504 @@
+use std::{fs, path::Path};
+
+use anyhow::anyhow;
+use swc_atoms::JsWord;
+use swc_common::{FileName, DUMMY_SP};
+use swc_ecma_ast::{
+    BindingIdent, CallExpr, Callee, ComputedPropName, Decl, DefaultDecl, ExportSpecifier, Expr,
+    ExprOrSpread, ExprStmt, Ident, ImportDecl, ImportSpecifier, ImportStarAsSpecifier, Lit,
+    MemberExpr, MemberProp, Module, ModuleDecl, ModuleExportName, ModuleItem, NamedExport, Pat,
+    Stmt, VarDecl, VarDeclKind, VarDeclarator,
+};
+use swc_ecma_loader::resolve::Resolve;
+use swc_ecma_visit::{VisitMut, VisitMutWith};
+
+/// Entry module name of the hmr prelude, which sets up the react-refresh runtime
+pub const HMR_PRELUDE: &str = "__trunk_hmr__";
+
+/// The hmr prelude module. It's part of the bundle, so it gets bundled before react-dom loads
+pub const HMR_PRELUDE_SOURCE: &str = r#"import RefreshRuntime from "react-refresh/runtime";
+
+RefreshRuntime.injectIntoGlobalHook(window);
+window.__TRUNK_HMR__.runtime = RefreshRuntime;
+"#;
+
+/// Virtual path of the hmr prelude. It never exists on disk, but lives in the project root
+/// so `react-refresh` gets resolved from the project's `node_modules`
+pub fn prelude_path(root: &Path) -> std::path::PathBuf {
+    root.join(format!("{HMR_PRELUDE}.js"))
+}
+
+/// react-refresh registers components under the id `$RefreshReg$` was called with,
+/// which is only unique per module. Prefix the ids with the module path so they're
+/// unique across the bundle
+pub struct RefreshIds {
+    /// `module_id` of the module, so a full build and a hot update register the same ids
+    pub module_id: String,
+}
+
+impl VisitMut for RefreshIds {
+    fn visit_mut_call_expr(&mut self, call: &mut CallExpr) {
+        call.visit_mut_children_with(self);
+
+        let is_refresh_reg = matches!(
+            &call.callee,
+            Callee::Expr(callee) if matches!(&**callee, Expr::Ident(i) if &*i.sym == "$RefreshReg$")
+        );
+
+        if !is_refresh_reg {
+            return;
+        }
+
+        if let Some(Expr::Lit(Lit::Str(id))) = call.args.get_mut(1).map(|a| &mut *a.expr) {
+            id.value = format!("{} {}", self.module_id, id.value).into();
+            id.raw = None;
+        }
+    }
+}
+
+/// Id of a module in the hmr registry. A full build and a hot update resolve imports of the same
+/// module to the same id
+pub fn module_id(file: &FileName) -> String {
+    match file {
+        FileName::Real(path) => fs::canonicalize(path)
+            .unwrap_or_else(|_| path.clone())
+            .display()
+            .to_string(),
+        _ => file.to_string(),
+    }
+}
+
+/// Resolves what modules import to their registry ids. External modules aren't resolved,
+/// their specifier is the id
+pub struct Registry<'a> {
+    pub base: &'a FileName,
+    pub resolver: &'a dyn Resolve,
+    pub external_modules: &'a [JsWord],
+}
+
+impl Registry<'_> {
+    fn id(&self, src: &JsWord) -> Option<String> {
+        if self.external_modules.contains(src) {
+            return Some(src.to_string());
+        }
+
+        let file = self.resolver.resolve(self.base, src).ok()?;
+        Some(module_id(&file))
+    }
+
+    /// Registers the namespace of every module a project module imports, as
+    /// `window.__TRUNK_HMR__.register(id, namespace)`. Hot updates import them from there,
+    /// instead of bundling a second copy of e.g. react
+    pub fn register_imports(&self, module: &mut Module) {
+        let mut sources = vec![];
+        for item in &module.body {
+            let src = match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(i)) if !i.type_only => &i.src,
+                ModuleItem::ModuleDecl(ModuleDecl::ExportAll(e)) if !e.type_only => &e.src,
+                ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(NamedExport {
+                    src: Some(src),
+                    type_only: false,
+                    ..
+                })) => src,
+                _ => continue,
+            };
+
+            if !sources.contains(&src.value) {
+                sources.push(src.value.clone());
+            }
+        }
+
+        for (i, src) in sources.into_iter().enumerate() {
+            let Some(id) = self.id(&src) else {
+                continue;
+            };
+
+            let local = Ident::new(format!("__trunk_hmr_{i}").into(), DUMMY_SP);
+            module
+                .body
+                .push(ModuleItem::ModuleDecl(ModuleDecl::Import(ImportDecl {
+                    span: DUMMY_SP,
+                    specifiers: vec![ImportSpecifier::Namespace(ImportStarAsSpecifier {
+                        span: DUMMY_SP,
+                        local: local.clone(),
+                    })],
+                    src: Box::new(src.into()),
+                    type_only: false,
+                    asserts: None,
+                })));
+            module.body.push(ModuleItem::Stmt(Stmt::Expr(ExprStmt {
+                span: DUMMY_SP,
+                expr: Box::new(registry_call("register", &id, vec![Expr::Ident(local)])),
+            })));
+        }
+    }
+
+    /// Rewrites the imports of a hot updated module to reads from the registry, e.g.
+    /// `import { useState } from "react"` to `var useState = window.__TRUNK_HMR__.import(id).useState`.
+    /// Side effect imports already ran in the page, and are dropped. Re-exports are left alone,
+    /// modules which have them aren't refresh boundaries
+    pub fn hot_imports(&self, module: &mut Module) -> anyhow::Result<()> {
+        let mut body = Vec::with_capacity(module.body.len());
+        for item in module.body.drain(..) {
+            let ModuleItem::ModuleDecl(ModuleDecl::Import(import)) = &item else {
+                body.push(item);
+                continue;
+            };
+            if import.type_only {
+                continue;
+            }
+
+            let id = self
+                .id(&import.src.value)
+                .ok_or_else(|| anyhow!("Failed to resolve {}", import.src.value))?;
+
+            for specifier in &import.specifiers {
+                let (local, init) = match specifier {
+                    ImportSpecifier::Default(s) => {
+                        (s.local.clone(), registry_call("importDefault", &id, vec![]))
+                    }
+                    ImportSpecifier::Namespace(s) => {
+                        (s.local.clone(), registry_call("import", &id, vec![]))
+                    }
+                    ImportSpecifier::Named(s) if s.is_type_only => continue,
+                    ImportSpecifier::Named(s) => {
+                        let imported = match &s.imported {
+                            Some(ModuleExportName::Ident(i)) => i.sym.clone(),
+                            Some(ModuleExportName::Str(s)) => s.value.clone(),
+                            None => s.local.sym.clone(),
+                        };
+                        let init = Expr::Member(MemberExpr {
+                            span: DUMMY_SP,
+                            obj: Box::new(registry_call("import", &id, vec![])),
+                            prop: MemberProp::Computed(ComputedPropName {
+                                span: DUMMY_SP,
+                                expr: Box::new(Expr::Lit(Lit::Str(imported.into()))),
+                            }),
+                        });
+                        (s.local.clone(), init)
+                    }
+                };
+
+                body.push(var(local, init));
+            }
+        }
+
+        module.body = body;
+        Ok(())
+    }
+}
+
+/// `window.__TRUNK_HMR__.method(id, ...args)`
+fn registry_call(method: &str, id: &str, args: Vec<Expr>) -> Expr {
+    let hmr = Expr::Member(MemberExpr {
+        span: DUMMY_SP,
+        obj: Box::new(Expr::Ident(Ident::new("window".into(), DUMMY_SP))),
+        prop: MemberProp::Ident(Ident::new("__TRUNK_HMR__".into(), DUMMY_SP)),
+    });
+    let callee = Expr::Member(MemberExpr {
+        span: DUMMY_SP,
+        obj: Box::new(hmr),
+        prop: MemberProp::Ident(Ident::new(method.into(), DUMMY_SP)),
+    });
+
+    let id = Expr::Lit(Lit::Str(id.into()));
+    Expr::Call(CallExpr {
+        span: DUMMY_SP,
+        callee: Callee::Expr(Box::new(callee)),
+        args: [id]
+            .into_iter()
+            .chain(args)
+            .map(|expr| ExprOrSpread {
+                spread: None,
+                expr: Box::new(expr),
+            })
+            .collect(),
+        type_args: None,
+    })
+}
+
+fn var(local: Ident, init: Expr) -> ModuleItem {
+    ModuleItem::Stmt(Stmt::Decl(Decl::Var(Box::new(VarDecl {
+        span: DUMMY_SP,
+        kind: VarDeclKind::Var,
+        declare: false,
+        decls: vec![VarDeclarator {
+            span: DUMMY_SP,
+            name: Pat::Ident(BindingIdent {
+                id: local,
+                type_ann: None,
+            }),
+            init: Some(Box::new(init)),
+            definite: false,
+        }],
+    }))))
+}
+
+/// Whether a module only exports react components, so react-refresh can swap it in place.
+/// Like react-refresh, components are told apart by their uppercase names. Type exports don't count,
+/// and a module without exports, or with re-exports, isn't a boundary
+pub fn exports_only_components(module: &Module) -> bool {
+    let mut has_exports = false;
+
+    for item in &module.body {
+        let ModuleItem::ModuleDecl(decl) = item else {
+            continue;
+        };
+
+        let is_component = match decl {
+            ModuleDecl::Import(_) => continue,
+            ModuleDecl::ExportDecl(export) => match &export.decl {
+                Decl::TsInterface(_) | Decl::TsTypeAlias(_) => continue,
+                Decl::Fn(f) => is_component_name(&f.ident.sym),
+                Decl::Class(c) => is_component_name(&c.ident.sym),
+                Decl::Var(var) => var.decls.iter().all(|d| {
+                    let is_named = matches!(&d.name, Pat::Ident(i) if is_component_name(&i.id.sym));
+                    is_named && d.init.as_deref().is_some_and(is_component_expr)
+                }),
+                _ => false,
+            },
+            ModuleDecl::ExportNamed(NamedExport {
+                type_only: true, ..
+            }) => continue,
+            ModuleDecl::ExportNamed(NamedExport {
+                src: None,
+                specifiers,
+                ..
+            }) => specifiers.iter().all(|s| match s {
+                ExportSpecifier::Named(s) if s.is_type_only => true,
+                ExportSpecifier::Named(s) => {
+                    let name = |n: &ModuleExportName| match n {
+                        ModuleExportName::Ident(i) => i.sym.clone(),
+                        ModuleExportName::Str(s) => s.value.clone(),
+                    };
+                    let exported = s.exported.as_ref().map(name);
+                    match exported {
+                        Some(exported) if &*exported != "default" => is_component_name(&exported),
+                        _ => is_component_name(&name(&s.orig)),
+                    }
+                }
+                _ => false,
+            }),
+            ModuleDecl::ExportDefaultDecl(export) => match &export.decl {
+                DefaultDecl::TsInterfaceDecl(_) => continue,
+                DefaultDecl::Fn(f) => f.ident.as_ref().map_or(true, |i| is_component_name(&i.sym)),
+                DefaultDecl::Class(c) => {
+                    c.ident.as_ref().map_or(true, |i| is_component_name(&i.sym))
+                }
+            },
+            ModuleDecl::ExportDefaultExpr(export) => match &*export.expr {
+                Expr::Ident(i) => is_component_name(&i.sym),
+                expr => is_component_expr(expr),
+            },
+            _ => false,
+        };
+
+        if !is_component {
+            return false;
+        }
+        has_exports = true;
+    }
+
+    has_exports
+}
+
+fn is_component_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+/// Functions, classes, and `memo()`/`forwardRef()` wrapped components
+fn is_component_expr(expr: &Expr) -> bool {
+    match expr {
+        Expr::Arrow(_) | Expr::Fn(_) | Expr::Class(_) => true,
+        Expr::Paren(p) => is_component_expr(&p.expr),
+        Expr::Call(CallExpr {
+            callee: Callee::Expr(callee),
+            ..
+        }) => {
+            let name = match &**callee {
+                Expr::Ident(i) => &i.sym,
+                Expr::Member(MemberExpr {
+                    prop: MemberProp::Ident(i),
+                    ..
+                }) => &i.sym,
+                _ => return false,
+            };
+            matches!(&**name, "memo" | "forwardRef")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use swc_common::SourceMap;
+    use swc_ecma_codegen::{text_writer::JsWriter, Emitter};
+    use swc_ecma_parser::{parse_file_as_module, EsConfig, Syntax, TsConfig};
+
+    use super::*;
+
+    thread_local! {
+        static CM: Arc<SourceMap> = Default::default();
+    }
+
+    fn parse(source: &str, syntax: Syntax) -> Module {
+        let fm = CM.with(|cm| cm.new_source_file(FileName::Anon, source.to_owned()));
+        parse_file_as_module(&fm, syntax, Default::default(), None, &mut vec![]).unwrap()
+    }
+
+    fn jsx(source: &str) -> Module {
+        parse(
+            source,
+            Syntax::Es(EsConfig {
+                jsx: true,
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn print(module: &Module) -> String {
+        let cm = CM.with(Arc::clone);
+        let mut output = vec![];
+        let mut emitter = Emitter {
+            cfg: Default::default(),
+            cm: cm.clone(),
+            comments: None,
+            wr: JsWriter::new(cm, "\n", &mut output, None),
+        };
+        emitter.emit_module(module).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Resolves everything to `/project/<specifier>`
+    struct TestResolver;
+
+    impl Resolve for TestResolver {
+        fn resolve(&self, _: &FileName, src: &str) -> anyhow::Result<FileName> {
+            match src.strip_prefix("./") {
+                Some(file) => Ok(FileName::Real(PathBuf::from("/project/src").join(file))),
+                None => Ok(FileName::Real(
+                    PathBuf::from("/project/node_modules").join(src),
+                )),
+            }
+        }
+    }
+
+    fn registry<'a>(base: &'a FileName, external_modules: &'a [JsWord]) -> Registry<'a> {
+        Registry {
+            base,
+            resolver: &TestResolver,
+            external_modules,
+        }
+    }
+
+    #[test]
+    fn components_are_refresh_boundaries() {
+        for source in [
+            "export default function App() { return <div />; }",
+            "export default function () { return <div />; }",
+            "export function Header() {} export const Footer = () => null;",
+            "const List = memo(() => null); export { List, List as default };",
+            "export const Input = React.forwardRef((props, ref) => null);",
+            "export class Page extends Component {}",
+            "import { useState } from 'react'; const Counter = () => null; export default Counter;",
+        ] {
+            assert!(exports_only_components(&jsx(source)), "{source}");
+        }
+    }
+
+    #[test]
+    fn other_exports_are_not_refresh_boundaries() {
+        for source in [
+            "",
+            "import './app';",
+            "export function useThing() {}",
+            "export const App = () => null; export const config = {};",
+            "export const Theme = createContext();",
+            "export const API = 'https://example.com';",
+            "export * from './components';",
+            "export { Button } from './button';",
+            "export default 42;",
+            "export default function render() {}",
+        ] {
+            assert!(!exports_only_components(&jsx(source)), "{source}");
+        }
+    }
+
+    #[test]
+    fn type_exports_dont_count() {
+        let module = parse(
+            "export interface Props {} export type Size = 'sm'; export default function Card(props: Props) {}",
+            Syntax::Typescript(TsConfig {
+                tsx: true,
+                ..Default::default()
+            }),
+        );
+
+        assert!(exports_only_components(&module));
+    }
+
+    #[test]
+    fn registers_imported_namespaces() {
+        let base = FileName::Real("/project/src/app.jsx".into());
+        let external = ["preact".into()];
+        let mut module = jsx(
+            "import React, { useState } from 'react'; import './button'; import { h } from 'preact'; import { x } from 'react';",
+        );
+
+        registry(&base, &external).register_imports(&mut module);
+
+        let output = print(&module);
+        assert!(output.ends_with(
+            r#"import * as __trunk_hmr_0 from "react";
+window.__TRUNK_HMR__.register("/project/node_modules/react", __trunk_hmr_0);
+import * as __trunk_hmr_1 from "./button";
+window.__TRUNK_HMR__.register("/project/src/button", __trunk_hmr_1);
+import * as __trunk_hmr_2 from "preact";
+window.__TRUNK_HMR__.register("preact", __trunk_hmr_2);
+"#
+        ));
+    }
+
+    #[test]
+    fn hot_updates_import_from_the_registry() {
+        let base = FileName::Real("/project/src/app.jsx".into());
+        let mut module = jsx(
+            "import React, { useState as state, 'a-b' as ab } from 'react'; import * as utils from './utils'; import './styles'; export default function App() {}",
+        );
+
+        registry(&base, &[]).hot_imports(&mut module).unwrap();
+
+        assert_eq!(
+            print(&module),
+            r#"var React = window.__TRUNK_HMR__.importDefault("/project/node_modules/react");
+var state = window.__TRUNK_HMR__.import("/project/node_modules/react")["useState"];
+var ab = window.__TRUNK_HMR__.import("/project/node_modules/react")["a-b"];
+var utils = window.__TRUNK_HMR__.import("/project/src/utils");
+export default function App() {}
+"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refresh_ids_use_the_module_id() {
+        let dir = crate::helpers::TempDir::new("refresh_ids", &[("src/App.jsx", "")]);
+        let link = dir.0.join("linked");
+        std::os::unix::fs::symlink(dir.0.join("src"), &link).unwrap();
+        let mut module = jsx(r#"$RefreshReg$(_c, "App");"#);
+
+        // a symlinked path registers the same ids as the real one, which hot updates resolve to
+        let id = module_id(&FileName::Real(link.join("App.jsx")));
+        module.visit_mut_with(&mut RefreshIds { module_id: id });
+
+        assert_eq!(
+            print(&module),
+            format!(
+                "$RefreshReg$(_c, \"{} App\");\n",
+                dir.0.join("src/App.jsx").display()
+            )
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use swc_common::{collections::AHashMap, FileName};
+use swc_ecma_loader::{resolve::Resolve, resolvers::node::NodeModulesResolver};
+
+use super::{
+    package::PackageResolver, shims::NodeShimResolver, tsconfig::TsConfigJson,
+    workspace::is_dependency,
+};
+
+/// The resolvers a bundle is resolved with, outermost first
+pub type ProjectResolver = AliasResolver<NodeShimResolver<PackageResolver<NodeModulesResolver>>>;
+
+/// Rewrites aliased imports before handing them to `inner`
+///
+/// `SwcrcConfig::alias` applies to every import. `tsconfig.json` `paths` and `baseUrl`
+/// only apply to the project's own files, like they do in `tsc`
+pub struct AliasResolver<R> {
+    inner: R,
+    /// Sorted by length, so the most specific alias wins
+    aliases: Vec<(String, String)>,
+    base_url: Option<PathBuf>,
+    paths: Vec<(String, Vec<PathBuf>)>,
+}
+
+impl<R: Resolve> AliasResolver<R> {
+    pub fn new(
+        inner: R,
+        root: &Path,
+        aliases: Option<AHashMap<String, String>>,
+        tsconfig: Option<&TsConfigJson>,
+    ) -> Self {
+        // relative alias targets are relative to the project root, anything else is a package
+        let mut aliases = aliases
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(from, to)| {
+                if to.starts_with('.') {
+                    (from, root.join(to).display().to_string())
+                } else {
+                    (from, to)
+                }
+            })
+            .collect::<Vec<_>>();
+        aliases.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+        let compiler_options = tsconfig.map(|t| &t.compiler_options);
+        let base_url = compiler_options.and_then(|c| c.base_url.clone());
+
+        let mut paths = vec![];
+        if let Some(options) = compiler_options {
+            let paths_base = options.paths_base.as_deref().unwrap_or(root);
+            for (pattern, targets) in options.paths.iter().flatten() {
+                let targets = targets.iter().map(|t| paths_base.join(t)).collect();
+                paths.push((pattern.clone(), targets));
+            }
+        }
+        // like `tsc`, the longest prefix before the `*` wins
+        paths.sort_by(|(a, _), (b, _)| {
+            let prefix = |p: &str| p.find('*').unwrap_or(p.len());
+            prefix(b).cmp(&prefix(a)).then(a.cmp(b))
+        });
+
+        Self {
+            inner,
+            aliases,
+            base_url,
+            paths,
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for AliasResolver<R> {
+    fn resolve(&self, base: &FileName, module_specifier: &str) -> Result<FileName, Error> {
+        let is_relative = module_specifier.starts_with("./")
+            || module_specifier.starts_with("../")
+            || module_specifier == "."
+            || module_specifier == "..";
+        if is_relative || Path::new(module_specifier).is_absolute() {
+            return self.inner.resolve(base, module_specifier);
+        }
+
+        for (from, to) in &self.aliases {
+            if let Some(rest) = module_specifier.strip_prefix(&**from) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    return self.inner.resolve(base, &format!("{to}{rest}"));
+                }
+            }
+        }
+
+        if matches!(base, FileName::Real(path) if is_dependency(path)) {
+            return self.inner.resolve(base, module_specifier);
+        }
+
+        for (pattern, targets) in &self.paths {
+            let Some(matched) = match_pattern(pattern, module_specifier) else {
+                continue;
+            };
+
+            for target in targets {
+                let target = target.display().to_string().replacen('*', matched, 1);
+                if let Ok(file) = self.inner.resolve(base, &target) {
+                    return Ok(file);
+                }
+            }
+        }
+
+        if let Some(base_url) = &self.base_url {
+            let target = base_url.join(module_specifier);
+            if let Ok(file) = self.inner.resolve(base, &target.display().to_string()) {
+                return Ok(file);
+            }
+        }
+
+        self.inner.resolve(base, module_specifier)
+    }
+}
+
+/// Match a `paths` pattern with at most one `*`, returning what the `*` matched
+fn match_pattern<'a>(pattern: &str, specifier: &'a str) -> Option<&'a str> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => specifier.strip_prefix(prefix)?.strip_suffix(suffix),
+        None => (pattern == specifier).then_some(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_paths_patterns() {
+        assert_eq!(
+            match_pattern("@/*", "@/components/button"),
+            Some("components/button")
+        );
+        assert_eq!(match_pattern("*.css", "theme.css"), Some("theme"));
+        assert_eq!(
+            match_pattern("@app/*/index", "@app/home/index"),
+            Some("home")
+        );
+        assert_eq!(match_pattern("*", "react"), Some("react"));
+        assert_eq!(match_pattern("config", "config"), Some(""));
+
+        assert_eq!(match_pattern("@/*", "react"), None);
+        assert_eq!(match_pattern("config", "config/dev"), None);
+        assert_eq!(match_pattern("@app/*/index", "@app/home"), None);
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::Error;
+use swc_atoms::JsWord;
+use swc_common::FileName;
+use swc_ecma_ast::{BindingIdent, ClassDecl, Expr, FnDecl, ImportSpecifier, Module};
+use swc_ecma_loader::resolve::Resolve;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use super::package::EMPTY_MODULE;
+
+/// Node built-ins, and the npm packages which polyfill them for the browser
+const POLYFILLS: &[(&str, Option<&str>)] = &[
+    ("assert", Some("assert")),
+    ("buffer", Some("buffer")),
+    ("child_process", None),
+    ("cluster", None),
+    ("console", Some("console-browserify")),
+    ("constants", Some("constants-browserify")),
+    ("crypto", Some("crypto-browserify")),
+    ("dgram", None),
+    ("dns", None),
+    ("domain", Some("domain-browser")),
+    ("events", Some("events")),
+    ("fs", None),
+    ("http", Some("stream-http")),
+    ("https", Some("https-browserify")),
+    ("module", None),
+    ("net", None),
+    ("os", Some("os-browserify/browser")),
+    ("path", Some("path-browserify")),
+    ("process", Some("process/browser")),
+    ("punycode", Some("punycode")),
+    ("querystring", Some("querystring-es3")),
+    ("readline", None),
+    ("stream", Some("stream-browserify")),
+    ("string_decoder", Some("string_decoder")),
+    ("sys", Some("util")),
+    ("timers", Some("timers-browserify")),
+    ("tls", None),
+    ("tty", Some("tty-browserify")),
+    ("url", Some("url")),
+    ("util", Some("util")),
+    ("vm", Some("vm-browserify")),
+    ("worker_threads", None),
+    ("zlib", Some("browserify-zlib")),
+];
+
+/// Minimal stand-ins for the most used built-ins, when their polyfill isn't installed
+const STUBS: &[(&str, &str)] = &[
+    ("node:buffer", include_str!("shims/buffer.js")),
+    ("node:events", include_str!("shims/events.js")),
+    ("node:path", include_str!("shims/path.js")),
+    ("node:process", include_str!("shims/process.js")),
+];
+
+/// Node globals, and the code which defines them for a module
+const GLOBALS: &[(&str, &str)] = &[
+    ("process", r#"var process = require("process");"#),
+    ("Buffer", r#"var Buffer = require("buffer").Buffer;"#),
+    ("global", "var global = globalThis;"),
+];
+
+/// Source of a built-in stub, by its `FileName::Custom` name
+pub fn stub_source(name: &str) -> Option<&'static str> {
+    STUBS.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+}
+
+/// Maps Node built-ins to browser polyfills when `enabled`. Built-ins resolve to the package
+/// of the same name when it's installed, then to the usual polyfill package, then to a stub.
+/// Built-ins without a stub become empty modules
+pub struct NodeShimResolver<R> {
+    inner: R,
+    enabled: bool,
+}
+
+impl<R: Resolve> NodeShimResolver<R> {
+    pub fn new(inner: R, enabled: bool) -> Self {
+        Self { inner, enabled }
+    }
+}
+
+impl<R: Resolve> Resolve for NodeShimResolver<R> {
+    fn resolve(&self, base: &FileName, module_specifier: &str) -> Result<FileName, Error> {
+        let name = module_specifier
+            .strip_prefix("node:")
+            .unwrap_or(module_specifier);
+        let polyfill = POLYFILLS.iter().find(|(n, _)| *n == name);
+
+        let Some((name, polyfill)) = polyfill.filter(|_| self.enabled) else {
+            return self.inner.resolve(base, module_specifier);
+        };
+
+        // the browser field, or an installed package of the same name
+        if let Ok(file) = self.inner.resolve(base, name) {
+            return Ok(file);
+        }
+
+        if let Some(Ok(file)) = polyfill.map(|p| self.inner.resolve(base, p)) {
+            return Ok(file);
+        }
+
+        let stub = format!("node:{name}");
+        if stub_source(&stub).is_some() {
+            Ok(FileName::Custom(stub))
+        } else {
+            Ok(FileName::Custom(EMPTY_MODULE.to_owned()))
+        }
+    }
+}
+
+/// Code to prepend to a module, which defines the Node globals it uses
+pub fn globals_prelude(module: &Module) -> String {
+    let mut finder = FreeGlobals::default();
+    module.visit_with(&mut finder);
+
+    GLOBALS
+        .iter()
+        .filter(|(name, _)| {
+            finder.used.iter().any(|u| u == *name) && !finder.declared.iter().any(|d| d == *name)
+        })
+        .map(|(_, code)| *code)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Finds the Node globals a module references, and everything it declares. Scopes are ignored,
+/// so a global is only considered free if the name isn't declared anywhere in the module
+#[derive(Default)]
+struct FreeGlobals {
+    used: Vec<JsWord>,
+    declared: Vec<JsWord>,
+}
+
+impl Visit for FreeGlobals {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Ident(ident) = expr {
+            if GLOBALS.iter().any(|(name, _)| ident.sym == **name) {
+                self.used.push(ident.sym.clone());
+            }
+        }
+
+        expr.visit_children_with(self);
+    }
+
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        self.declared.push(ident.id.sym.clone());
+    }
+
+    fn visit_fn_decl(&mut self, decl: &FnDecl) {
+        self.declared.push(decl.ident.sym.clone());
+        decl.visit_children_with(self);
+    }
+
+    fn visit_class_decl(&mut self, decl: &ClassDecl) {
+        self.declared.push(decl.ident.sym.clone());
+        decl.visit_children_with(self);
+    }
+
+    fn visit_import_specifier(&mut self, specifier: &ImportSpecifier) {
+        let local = match specifier {
+            ImportSpecifier::Named(s) => &s.local,
+            ImportSpecifier::Default(s) => &s.local,
+            ImportSpecifier::Namespace(s) => &s.local,
+        };
+        self.declared.push(local.sym.clone());
+    }
+}
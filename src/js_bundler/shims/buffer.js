@@ -0,0 +1,46 @@
+// minimal `Buffer` for browser bundles, used when the `buffer` package isn't installed.
+// Only utf8 strings, arrays and array buffers are supported
+function Buffer(arg, offset, length) {
+    if (typeof arg === "number") return new Uint8Array(arg);
+    return Buffer.from(arg, offset, length);
+}
+
+Buffer.from = function (value, offset, length) {
+    var bytes;
+    if (typeof value === "string") bytes = new TextEncoder().encode(value);
+    else if (value instanceof ArrayBuffer) bytes = new Uint8Array(value, offset || 0, length);
+    else bytes = Uint8Array.from(value);
+    Object.setPrototypeOf(bytes, Buffer.prototype);
+    return bytes;
+};
+
+Buffer.alloc = function (size) {
+    return Buffer.from(new Uint8Array(size));
+};
+
+// not `instanceof`, every `require` of this module gets its own `Buffer`
+Buffer.isBuffer = function (value) {
+    return value != null && value._isBuffer === true;
+};
+
+Buffer.concat = function (list) {
+    var size = list.reduce(function (sum, b) {
+        return sum + b.length;
+    }, 0);
+    var result = new Uint8Array(size);
+    var offset = 0;
+    for (var i = 0; i < list.length; i++) {
+        result.set(list[i], offset);
+        offset += list[i].length;
+    }
+    return Buffer.from(result);
+};
+
+Buffer.prototype = Object.create(Uint8Array.prototype);
+Buffer.prototype.constructor = Buffer;
+Buffer.prototype._isBuffer = true;
+Buffer.prototype.toString = function () {
+    return new TextDecoder().decode(this);
+};
+
+module.exports = { Buffer: Buffer };
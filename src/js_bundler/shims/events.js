@@ -0,0 +1,50 @@
+// minimal `events` for browser bundles, used when the `events` package isn't installed
+function EventEmitter() {
+    this._events = {};
+}
+
+EventEmitter.prototype.on = EventEmitter.prototype.addListener = function (name, listener) {
+    (this._events[name] = this._events[name] || []).push(listener);
+    return this;
+};
+
+EventEmitter.prototype.once = function (name, listener) {
+    var self = this;
+    function once() {
+        self.off(name, once);
+        listener.apply(self, arguments);
+    }
+    once.listener = listener;
+    return this.on(name, once);
+};
+
+EventEmitter.prototype.off = EventEmitter.prototype.removeListener = function (name, listener) {
+    var listeners = this._events[name] || [];
+    this._events[name] = listeners.filter(function (l) {
+        return l !== listener && l.listener !== listener;
+    });
+    return this;
+};
+
+EventEmitter.prototype.removeAllListeners = function (name) {
+    if (name === undefined) this._events = {};
+    else delete this._events[name];
+    return this;
+};
+
+EventEmitter.prototype.emit = function (name) {
+    var args = Array.prototype.slice.call(arguments, 1);
+    var listeners = (this._events[name] || []).slice();
+    for (var i = 0; i < listeners.length; i++) listeners[i].apply(this, args);
+    return listeners.length > 0;
+};
+
+EventEmitter.prototype.listeners = function (name) {
+    return (this._events[name] || []).slice();
+};
+
+EventEmitter.EventEmitter = EventEmitter;
+// the bundler's require interop copies plain cjs exports into an object, which loses the constructor
+EventEmitter.__esModule = true;
+EventEmitter.default = EventEmitter;
+module.exports = EventEmitter;
@@ -0,0 +1,67 @@
+// minimal posix `path` for browser bundles, used when `path-browserify` isn't installed
+function normalizeParts(parts, absolute) {
+    var out = [];
+    for (var i = 0; i < parts.length; i++) {
+        var part = parts[i];
+        if (!part || part === ".") continue;
+        if (part === "..") {
+            if (out.length && out[out.length - 1] !== "..") out.pop();
+            else if (!absolute) out.push("..");
+        } else out.push(part);
+    }
+    return out;
+}
+
+function normalize(path) {
+    var absolute = path.charAt(0) === "/";
+    var trailing = path.slice(-1) === "/";
+    var result = normalizeParts(path.split("/"), absolute).join("/");
+    if (!result && !absolute) result = ".";
+    if (result && trailing) result += "/";
+    return (absolute ? "/" : "") + result;
+}
+
+function join() {
+    return normalize(Array.prototype.filter.call(arguments, Boolean).join("/"));
+}
+
+function resolve() {
+    var path = "";
+    for (var i = arguments.length - 1; i >= 0 && path.charAt(0) !== "/"; i--) {
+        if (arguments[i]) path = arguments[i] + "/" + path;
+    }
+    return "/" + normalizeParts(path.split("/"), true).join("/");
+}
+
+function dirname(path) {
+    var index = path.replace(/\/+$/, "").lastIndexOf("/");
+    if (index < 0) return ".";
+    return index === 0 ? "/" : path.slice(0, index);
+}
+
+function basename(path, ext) {
+    var base = path.replace(/\/+$/, "").split("/").pop();
+    if (ext && base.slice(-ext.length) === ext) base = base.slice(0, -ext.length);
+    return base;
+}
+
+function extname(path) {
+    var base = basename(path);
+    var index = base.lastIndexOf(".");
+    return index > 0 ? base.slice(index) : "";
+}
+
+module.exports = {
+    sep: "/",
+    delimiter: ":",
+    normalize: normalize,
+    join: join,
+    resolve: resolve,
+    dirname: dirname,
+    basename: basename,
+    extname: extname,
+    isAbsolute: function (path) {
+        return path.charAt(0) === "/";
+    },
+};
+module.exports.posix = module.exports;
@@ -0,0 +1,25 @@
+// minimal `process` for browser bundles, used when the `process` package isn't installed
+var process = {
+    title: "browser",
+    browser: true,
+    env: {},
+    argv: [],
+    version: "",
+    versions: {},
+    platform: "browser",
+    nextTick: function (fn) {
+        var args = Array.prototype.slice.call(arguments, 1);
+        queueMicrotask(function () {
+            fn.apply(null, args);
+        });
+    },
+    cwd: function () {
+        return "/";
+    },
+    on: function () {},
+    once: function () {},
+    off: function () {},
+    emit: function () {},
+};
+
+module.exports = process;
@@ -0,0 +1,463 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use swc_atoms::JsWord;
+use swc_common::{FileName, SourceMap, DUMMY_SP};
+use swc_ecma_ast::{
+    BindingIdent, ClassDecl, EsVersion, ExportSpecifier, Expr, FnDecl, Ident, ImportDecl,
+    ImportDefaultSpecifier, ImportNamedSpecifier, ImportSpecifier, Module, ModuleDecl,
+    ModuleExportName, ModuleItem, Str, TsEnumDecl,
+};
+use swc_ecma_loader::resolve::Resolve;
+use swc_ecma_parser::{parse_file_as_module, EsConfig, Syntax, TsConfig};
+use swc_ecma_visit::{Visit, VisitWith};
+
+use super::package::Packages;
+
+/// How many re-exporting modules are followed to find where an export is declared
+const MAX_DEPTH: usize = 16;
+
+/// What a module exports, as far as re-exports are concerned
+#[derive(Default)]
+struct Exports {
+    /// Names declared by the module itself
+    local: FxHashSet<JsWord>,
+    /// `export { name as exported } from "src"`, as `(exported, name, src)`
+    named: Vec<(JsWord, JsWord, JsWord)>,
+    /// `export * from "src"`
+    stars: Vec<JsWord>,
+}
+
+/// Exports of the modules re-exports were followed through, by path. `None` if it couldn't be parsed
+#[derive(Default)]
+pub struct Reexports {
+    cache: Mutex<FxHashMap<PathBuf, Option<Arc<Exports>>>>,
+}
+
+impl Reexports {
+    fn get(&self, file: &Path) -> Option<Arc<Exports>> {
+        if let Some(exports) = self.cache.lock().unwrap().get(file) {
+            return exports.clone();
+        }
+
+        let exports = parse_exports(file).map(Arc::new);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(file.to_owned(), exports.clone());
+
+        exports
+    }
+}
+
+/// Rewrites the imports of a module, for modules without side effects (see `Packages::has_side_effects`)
+///
+/// Imports of them which aren't used are dropped, and named imports through modules which only
+/// re-export (`export { Button } from "./button"`) go straight to the module declaring them.
+/// The bundler then never loads the modules nothing uses
+pub struct SideEffectFreeImports<'a> {
+    pub path: &'a Path,
+    pub resolver: &'a dyn Resolve,
+    pub packages: &'a Packages,
+    pub reexports: &'a Reexports,
+    pub external_modules: &'a [JsWord],
+}
+
+impl SideEffectFreeImports<'_> {
+    fn resolve(&self, base: &Path, specifier: &str) -> Option<PathBuf> {
+        match self
+            .resolver
+            .resolve(&FileName::Real(base.to_owned()), specifier)
+        {
+            Ok(FileName::Real(file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// The module to import `name` from instead of `file`, and what it's called there
+    fn declaring_module(&self, file: PathBuf, name: JsWord, depth: usize) -> (PathBuf, JsWord) {
+        if depth > MAX_DEPTH || self.packages.has_side_effects(&file) {
+            return (file, name);
+        }
+        let Some(exports) = self.reexports.get(&file) else {
+            return (file, name);
+        };
+        if exports.local.contains(&name) {
+            return (file, name);
+        }
+
+        let reexport = exports
+            .named
+            .iter()
+            .find(|(exported, ..)| *exported == name);
+        if let Some((_, orig, src)) = reexport {
+            return match self.resolve(&file, src) {
+                Some(target) => self.declaring_module(target, orig.clone(), depth + 1),
+                None => (file, name),
+            };
+        }
+
+        // `export *` never re-exports `default`
+        if name != *"default" {
+            for src in &exports.stars {
+                let Some(target) = self.resolve(&file, src) else {
+                    return (file, name);
+                };
+                if self.provides(&target, &name, depth + 1) {
+                    return self.declaring_module(target, name, depth + 1);
+                }
+            }
+        }
+
+        (file, name)
+    }
+
+    /// Whether a module exports `name`, when it's reached through `export *`
+    fn provides(&self, file: &Path, name: &JsWord, depth: usize) -> bool {
+        let Some(exports) = self.reexports.get(file).filter(|_| depth <= MAX_DEPTH) else {
+            return false;
+        };
+
+        exports.local.contains(name)
+            || exports.named.iter().any(|(exported, ..)| exported == name)
+            || exports.stars.iter().any(|src| {
+                self.resolve(file, src)
+                    .is_some_and(|target| self.provides(&target, name, depth + 1))
+            })
+    }
+
+    /// The imports to replace an import of a module without side effects with
+    fn rewrite(&self, import: &ImportDecl, used: &FxHashSet<JsWord>) -> Option<Vec<ImportDecl>> {
+        if import.type_only || self.external_modules.contains(&import.src.value) {
+            return None;
+        }
+
+        let file = self.resolve(self.path, &import.src.value)?;
+        if self.packages.has_side_effects(&file) {
+            return None;
+        }
+
+        let mut kept = vec![];
+        let mut redirected = Vec::<ImportDecl>::new();
+        for specifier in &import.specifiers {
+            let (local, imported) = match specifier {
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(ModuleExportName::Ident(i)) => i.sym.clone(),
+                        Some(ModuleExportName::Str(s)) => s.value.clone(),
+                        None => s.local.sym.clone(),
+                    };
+                    (&s.local, Some(imported))
+                }
+                ImportSpecifier::Default(s) => (&s.local, Some("default".into())),
+                ImportSpecifier::Namespace(s) => (&s.local, None),
+            };
+
+            if !used.contains(&local.sym) {
+                continue;
+            }
+
+            let Some(imported) = imported else {
+                kept.push(specifier.clone());
+                continue;
+            };
+
+            let (target, name) = self.declaring_module(file.clone(), imported, 0);
+            if target == file {
+                kept.push(specifier.clone());
+                continue;
+            }
+
+            let specifier = if name == *"default" {
+                ImportSpecifier::Default(ImportDefaultSpecifier {
+                    span: DUMMY_SP,
+                    local: local.clone(),
+                })
+            } else {
+                ImportSpecifier::Named(ImportNamedSpecifier {
+                    span: DUMMY_SP,
+                    local: local.clone(),
+                    imported: Some(ModuleExportName::Ident(Ident::new(name, DUMMY_SP))),
+                    is_type_only: false,
+                })
+            };
+
+            let src: JsWord = target.display().to_string().into();
+            match redirected.iter_mut().find(|i| i.src.value == src) {
+                Some(import) => import.specifiers.push(specifier),
+                None => redirected.push(ImportDecl {
+                    span: DUMMY_SP,
+                    specifiers: vec![specifier],
+                    src: Box::new(Str::from(src)),
+                    type_only: false,
+                    asserts: None,
+                }),
+            }
+        }
+
+        // the module itself only stays when something is still imported from it
+        if !kept.is_empty() {
+            redirected.insert(
+                0,
+                ImportDecl {
+                    specifiers: kept,
+                    ..import.clone()
+                },
+            );
+        }
+
+        Some(redirected)
+    }
+
+    pub fn apply(&self, module: &mut Module) {
+        let mut used = UsedNames::default();
+        for item in &module.body {
+            if !matches!(item, ModuleItem::ModuleDecl(ModuleDecl::Import(_))) {
+                item.visit_with(&mut used);
+            }
+        }
+
+        let mut body = Vec::with_capacity(module.body.len());
+        for item in module.body.drain(..) {
+            let ModuleItem::ModuleDecl(ModuleDecl::Import(import)) = &item else {
+                body.push(item);
+                continue;
+            };
+
+            match self.rewrite(import, &used.names) {
+                Some(imports) => body.extend(
+                    imports
+                        .into_iter()
+                        .map(|i| ModuleItem::ModuleDecl(ModuleDecl::Import(i))),
+                ),
+                None => body.push(item),
+            }
+        }
+
+        module.body = body;
+    }
+}
+
+/// Every name referenced in a module. Scopes are ignored, so a shadowed import still counts as used
+#[derive(Default)]
+struct UsedNames {
+    names: FxHashSet<JsWord>,
+}
+
+impl Visit for UsedNames {
+    fn visit_ident(&mut self, ident: &Ident) {
+        self.names.insert(ident.sym.clone());
+    }
+}
+
+fn parse_exports(file: &Path) -> Option<Exports> {
+    let extension = file.extension()?.to_string_lossy().to_ascii_lowercase();
+    let syntax = match &*extension {
+        "ts" | "mts" | "cts" => Syntax::Typescript(Default::default()),
+        "tsx" => Syntax::Typescript(TsConfig {
+            tsx: true,
+            ..Default::default()
+        }),
+        "js" | "mjs" | "cjs" | "jsx" => Syntax::Es(EsConfig {
+            jsx: true,
+            ..Default::default()
+        }),
+        _ => return None,
+    };
+
+    // a separate source map, so these files don't count as loaded into the bundle
+    let cm = SourceMap::default();
+    let fm = cm.new_source_file(
+        FileName::Real(file.to_owned()),
+        fs::read_to_string(file).ok()?,
+    );
+    let module = parse_file_as_module(&fm, syntax, EsVersion::latest(), None, &mut vec![]).ok()?;
+
+    let mut exports = Exports::default();
+    for item in &module.body {
+        let ModuleItem::ModuleDecl(decl) = item else {
+            continue;
+        };
+
+        match decl {
+            ModuleDecl::ExportAll(all) => exports.stars.push(all.src.value.clone()),
+            ModuleDecl::ExportNamed(named) => {
+                for specifier in &named.specifiers {
+                    let (orig, exported) = match specifier {
+                        ExportSpecifier::Named(s) => {
+                            let exported = s.exported.as_ref().unwrap_or(&s.orig);
+                            (export_name(&s.orig), export_name(exported))
+                        }
+                        ExportSpecifier::Default(s) => ("default".into(), s.exported.sym.clone()),
+                        ExportSpecifier::Namespace(s) => {
+                            exports.local.insert(export_name(&s.name));
+                            continue;
+                        }
+                    };
+
+                    match &named.src {
+                        Some(src) => exports.named.push((exported, orig, src.value.clone())),
+                        None => {
+                            exports.local.insert(exported);
+                        }
+                    }
+                }
+            }
+            ModuleDecl::ExportDecl(decl) => {
+                let mut names = DeclaredNames::default();
+                decl.decl.visit_with(&mut names);
+                exports.local.extend(names.names);
+            }
+            ModuleDecl::ExportDefaultDecl(_) | ModuleDecl::ExportDefaultExpr(_) => {
+                exports.local.insert("default".into());
+            }
+            _ => {}
+        }
+    }
+
+    Some(exports)
+}
+
+fn export_name(name: &ModuleExportName) -> JsWord {
+    match name {
+        ModuleExportName::Ident(i) => i.sym.clone(),
+        ModuleExportName::Str(s) => s.value.clone(),
+    }
+}
+
+/// Names bound by an `export` declaration
+#[derive(Default)]
+struct DeclaredNames {
+    names: Vec<JsWord>,
+}
+
+impl Visit for DeclaredNames {
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        self.names.push(ident.id.sym.clone());
+    }
+
+    fn visit_fn_decl(&mut self, decl: &FnDecl) {
+        self.names.push(decl.ident.sym.clone());
+    }
+
+    fn visit_class_decl(&mut self, decl: &ClassDecl) {
+        self.names.push(decl.ident.sym.clone());
+    }
+
+    fn visit_ts_enum_decl(&mut self, decl: &TsEnumDecl) {
+        self.names.push(decl.id.sym.clone());
+    }
+
+    // initializers and default values don't declare anything
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use swc_ecma_loader::{resolvers::node::NodeModulesResolver, TargetEnv};
+
+    use super::*;
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn drops_and_redirects_imports_of_side_effect_free_packages() {
+        let dir = TempDir::new(
+            "side_effects_imports",
+            &[
+                ("src/app.js", ""),
+                (
+                    "node_modules/ui/package.json",
+                    r#"{ "name": "ui", "main": "index.js", "sideEffects": false }"#,
+                ),
+                (
+                    "node_modules/ui/index.js",
+                    r#"export { Button } from "./button.js"; export * from "./card.js"; export const theme = {};"#,
+                ),
+                ("node_modules/ui/button.js", "export function Button() {}"),
+                ("node_modules/ui/card.js", "export function Card() {}"),
+                (
+                    "node_modules/fx/package.json",
+                    r#"{ "name": "fx", "main": "index.js", "sideEffects": true }"#,
+                ),
+                ("node_modules/fx/index.js", "export const fx = 1;"),
+            ],
+        );
+        let path = dir.0.join("src/app.js");
+        let cm = SourceMap::default();
+        let fm = cm.new_source_file(
+            FileName::Real(path.clone()),
+            r#"
+            import { Button, Card, theme, unused } from "ui";
+            import { fx } from "fx";
+            console.log(Button, Card, theme);
+            "#
+            .to_owned(),
+        );
+        let mut module = parse_file_as_module(
+            &fm,
+            Default::default(),
+            Default::default(),
+            None,
+            &mut vec![],
+        )
+        .unwrap();
+
+        let resolver = NodeModulesResolver::new(TargetEnv::Browser, Default::default(), true);
+        SideEffectFreeImports {
+            path: &path,
+            resolver: &resolver,
+            packages: &Packages::default(),
+            reexports: &Reexports::default(),
+            external_modules: &[],
+        }
+        .apply(&mut module);
+
+        let imports = module
+            .body
+            .iter()
+            .filter_map(|item| match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => Some(import),
+                _ => None,
+            })
+            .map(|import| {
+                // redirected imports are resolved paths, named relative to the working directory
+                let src = match Path::new(&*import.src.value).canonicalize() {
+                    Ok(file) => format!("/{}", file.strip_prefix(&dir.0).unwrap().display()),
+                    Err(_) => import.src.value.to_string(),
+                };
+                let locals = import
+                    .specifiers
+                    .iter()
+                    .map(|s| match s {
+                        ImportSpecifier::Named(s) => s.local.sym.to_string(),
+                        _ => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                (src, locals)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            imports,
+            [
+                // `theme` is declared by the package's index, `unused` is dropped
+                ("ui".to_owned(), vec!["theme".to_owned()]),
+                // through `export { .. } from` and `export * from`
+                (
+                    "/node_modules/ui/button.js".to_owned(),
+                    vec!["Button".to_owned()]
+                ),
+                (
+                    "/node_modules/ui/card.js".to_owned(),
+                    vec!["Card".to_owned()]
+                ),
+                // `sideEffects: true` packages are imported for their side effects, used or not
+                ("fx".to_owned(), vec!["fx".to_owned()]),
+            ]
+        );
+    }
+}
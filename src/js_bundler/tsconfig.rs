@@ -0,0 +1,256 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use swc_common::collections::AHashMap;
+use swc_ecma_transforms_react::{Options as JsxOptions, Runtime};
+
+use crate::{
+    errors::{ApplicationError, Result},
+    helpers::strip_jsonc,
+};
+
+/// The parts of `tsconfig.json` the bundler cares about
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TsConfigJson {
+    pub extends: Option<Extends>,
+    #[serde(default)]
+    pub compiler_options: CompilerOptions,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Extends {
+    One(String),
+    /// Typescript 5.0+. Later configs override earlier ones
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompilerOptions {
+    pub jsx: Option<String>,
+    pub jsx_factory: Option<String>,
+    pub jsx_fragment_factory: Option<String>,
+    pub jsx_import_source: Option<String>,
+    /// Made absolute when loaded
+    pub base_url: Option<PathBuf>,
+    pub paths: Option<AHashMap<String, Vec<String>>>,
+    /// What `paths` are relative to. That's `baseUrl` if set, or else the directory
+    /// of the tsconfig which defined `paths`
+    #[serde(skip)]
+    pub paths_base: Option<PathBuf>,
+}
+
+impl CompilerOptions {
+    /// Fill in the options which aren't set from `parent`
+    fn extend(self, parent: Self) -> Self {
+        let base_url = self.base_url.or(parent.base_url);
+        let (paths, paths_base) = match self.paths {
+            Some(paths) => (Some(paths), self.paths_base),
+            None => (parent.paths, parent.paths_base),
+        };
+
+        Self {
+            jsx: self.jsx.or(parent.jsx),
+            jsx_factory: self.jsx_factory.or(parent.jsx_factory),
+            jsx_fragment_factory: self.jsx_fragment_factory.or(parent.jsx_fragment_factory),
+            jsx_import_source: self.jsx_import_source.or(parent.jsx_import_source),
+            paths_base: base_url.clone().or(paths_base),
+            base_url,
+            paths,
+        }
+    }
+}
+
+impl TsConfigJson {
+    /// Read `tsconfig.json` from the project root, if there is one. `extends` chains are followed
+    pub fn load(root: &Path) -> Result<Option<Self>> {
+        let path = root.join("tsconfig.json");
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        Self::load_file(&path, &mut vec![]).map(Some)
+    }
+
+    fn load_file(path: &Path, seen: &mut Vec<PathBuf>) -> Result<Self> {
+        let path = fs::canonicalize(path)?;
+        if seen.contains(&path) {
+            let error = format!("Circular `extends` in {}", path.display());
+            return Err(ApplicationError::GeneralError(error.into()));
+        }
+        seen.push(path.clone());
+
+        let tsconfig = fs::read_to_string(&path)?;
+        let mut tsconfig: Self = serde_json::from_str(&strip_jsonc(&tsconfig))
+            .map_err(Into::<Box<dyn std::error::Error>>::into)?;
+
+        let dir = path.parent().ok_or(ApplicationError::PathError)?;
+        let compiler_options = &mut tsconfig.compiler_options;
+        compiler_options.base_url = compiler_options.base_url.as_ref().map(|b| dir.join(b));
+        if compiler_options.paths.is_some() {
+            compiler_options.paths_base = Some(dir.to_owned());
+        }
+
+        let extends = match tsconfig.extends.take() {
+            Some(Extends::One(extends)) => vec![extends],
+            Some(Extends::Many(extends)) => extends,
+            None => vec![],
+        };
+
+        // the last config in `extends` wins, so it's applied first
+        for extends in extends.iter().rev() {
+            let parent = Self::load_file(&resolve_extends(dir, extends)?, seen)?;
+            tsconfig.compiler_options = tsconfig.compiler_options.extend(parent.compiler_options);
+        }
+
+        Ok(tsconfig)
+    }
+
+    /// Jsx options as set in `tsconfig.json`. `options` takes priority over them
+    pub fn jsx_options(&self, options: JsxOptions) -> JsxOptions {
+        let compiler_options = &self.compiler_options;
+
+        let runtime = compiler_options.jsx.as_deref().and_then(|jsx| match jsx {
+            "react" => Some(Runtime::Classic),
+            "react-jsx" | "react-jsxdev" => Some(Runtime::Automatic),
+            _ => None,
+        });
+
+        JsxOptions {
+            runtime: options.runtime.or(runtime),
+            pragma: options
+                .pragma
+                .or_else(|| compiler_options.jsx_factory.clone()),
+            pragma_frag: options
+                .pragma_frag
+                .or_else(|| compiler_options.jsx_fragment_factory.clone()),
+            import_source: options
+                .import_source
+                .or_else(|| compiler_options.jsx_import_source.clone()),
+            ..options
+        }
+    }
+}
+
+/// `extends` is either a path relative to the config, or a config from an npm package
+fn resolve_extends(dir: &Path, extends: &str) -> Result<PathBuf> {
+    let with_json = |path: PathBuf| {
+        let mut json = path.clone().into_os_string();
+        json.push(".json");
+        [
+            path.clone(),
+            PathBuf::from(json),
+            path.join("tsconfig.json"),
+        ]
+    };
+
+    let candidates = if extends.starts_with('.') || Path::new(extends).is_absolute() {
+        with_json(dir.join(extends)).to_vec()
+    } else {
+        dir.ancestors()
+            .flat_map(|d| with_json(d.join("node_modules").join(extends)))
+            .collect()
+    };
+
+    candidates
+        .into_iter()
+        .find(|p| p.is_file())
+        .ok_or_else(|| ApplicationError::TsConfigNotFound(extends.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn options_override_the_ones_they_extend() {
+        let parent = CompilerOptions {
+            jsx: Some("react".into()),
+            jsx_factory: Some("h".into()),
+            base_url: Some("/parent".into()),
+            paths: Some([("@/*".into(), vec!["src/*".into()])].into_iter().collect()),
+            paths_base: Some("/parent".into()),
+            ..Default::default()
+        };
+        let child = CompilerOptions {
+            jsx: Some("react-jsx".into()),
+            ..Default::default()
+        };
+
+        let options = child.extend(parent);
+        assert_eq!(options.jsx.as_deref(), Some("react-jsx"));
+        assert_eq!(options.jsx_factory.as_deref(), Some("h"));
+        assert_eq!(options.base_url, Some("/parent".into()));
+        assert!(options.paths.is_some());
+        assert_eq!(options.paths_base, Some("/parent".into()));
+    }
+
+    #[test]
+    fn base_url_of_the_child_is_where_inherited_paths_are_relative_to() {
+        let parent = CompilerOptions {
+            paths: Some(Default::default()),
+            paths_base: Some("/parent".into()),
+            ..Default::default()
+        };
+        let child = CompilerOptions {
+            base_url: Some("/child".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(child.extend(parent).paths_base, Some("/child".into()));
+    }
+
+    #[test]
+    fn follows_extends_chains() {
+        let project = TempDir::new(
+            "tsconfig_extends",
+            &[
+                (
+                    "tsconfig.json",
+                    r#"{
+                        // the last config wins
+                        "extends": ["./configs/base", "@scope/config"],
+                        "compilerOptions": { "jsx": "react-jsx", },
+                    }"#,
+                ),
+                (
+                    "configs/base.json",
+                    r#"{ "compilerOptions": { "jsx": "react", "jsxFactory": "h", "jsxImportSource": "preact", "paths": { "@/*": ["src/*"] } } }"#,
+                ),
+                (
+                    "node_modules/@scope/config/tsconfig.json",
+                    r#"{ "compilerOptions": { "jsxImportSource": "solid-js", "baseUrl": "." } }"#,
+                ),
+            ],
+        );
+
+        let tsconfig = TsConfigJson::load(&project.0).unwrap().unwrap();
+        let options = &tsconfig.compiler_options;
+        assert_eq!(options.jsx.as_deref(), Some("react-jsx"));
+        assert_eq!(options.jsx_factory.as_deref(), Some("h"));
+        assert_eq!(options.jsx_import_source.as_deref(), Some("solid-js"));
+
+        let package = project.0.join("node_modules/@scope/config");
+        assert_eq!(options.base_url, Some(package.join(".")));
+        assert_eq!(options.paths_base, Some(package.join(".")));
+    }
+
+    #[test]
+    fn circular_extends_fail() {
+        let project = TempDir::new(
+            "tsconfig_circular",
+            &[
+                ("tsconfig.json", r#"{ "extends": "./other.json" }"#),
+                ("other.json", r#"{ "extends": "./tsconfig.json" }"#),
+            ],
+        );
+
+        assert!(TsConfigJson::load(&project.0).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use swc::config::PluginConfig;
+use swc_common::{comments::SingleThreadedComments, sync::Lrc, FileName, Mark, SourceMap};
+use swc_ecma_ast::Module;
+use swc_ecma_loader::{resolve::Resolve, resolvers::node::NodeModulesResolver, TargetEnv};
+
+use crate::errors::{ApplicationError, Result};
+
+/// Resolve a plugin name to a `.wasm` file. Like `.swcrc`, this can either be a path
+/// (here relative to the project root), or the name of an npm package
+pub fn resolve_plugin(root: &Path, name: &str) -> Result<String> {
+    let path = root.join(name);
+    if path.is_file() {
+        return Ok(path.display().to_string());
+    }
+
+    let resolver = NodeModulesResolver::new(TargetEnv::Node, Default::default(), true);
+    match resolver.resolve(&FileName::Real(root.to_owned()), name) {
+        Ok(FileName::Real(path)) => Ok(path.display().to_string()),
+        _ => Err(ApplicationError::PluginNotFound(name.to_owned())),
+    }
+}
+
+/// Compile the plugin modules into the module cache if they aren't in there already
+#[cfg(feature = "plugin")]
+pub fn load_plugins(plugins: &[PluginConfig], cache_root: &Option<String>) -> Result<()> {
+    use swc::config::{init_plugin_module_cache_once, PLUGIN_MODULE_CACHE};
+
+    init_plugin_module_cache_once(true, cache_root);
+
+    let mut cache = PLUGIN_MODULE_CACHE
+        .inner
+        .get()
+        .expect("Cache should be available")
+        .lock();
+
+    for PluginConfig(path, _) in plugins {
+        if !cache.contains(path) {
+            cache
+                .store_bytes_from_path(Path::new(path), path)
+                .map_err(Into::<Box<dyn std::error::Error>>::into)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Without the `plugin` feature, configured plugins are an error, as the output would be
+/// missing their transforms
+#[cfg(not(feature = "plugin"))]
+pub fn load_plugins(plugins: &[PluginConfig], _cache_root: &Option<String>) -> Result<()> {
+    if plugins.is_empty() {
+        return Ok(());
+    }
+
+    let names = plugins
+        .iter()
+        .map(|PluginConfig(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(ApplicationError::GeneralError(
+        format!(
+            "trunk_bundler was built without the `plugin` feature, so the wasm plugins {names} can't run"
+        )
+        .into(),
+    ))
+}
+
+/// Run the plugins over a module, in order. Like `.swcrc`, the module has to be resolved with
+/// `unresolved_mark` already, and plugins can read and add to its comments
+#[cfg(feature = "plugin")]
+pub fn apply_plugins(
+    plugins: &[PluginConfig],
+    path: &Path,
+    module: Module,
+    comments: &SingleThreadedComments,
+    unresolved_mark: Mark,
+    cm: Lrc<SourceMap>,
+    debug: bool,
+) -> anyhow::Result<Module> {
+    use std::sync::Arc;
+
+    use anyhow::Context;
+    use swc::config::PLUGIN_MODULE_CACHE;
+    use swc_common::plugin::{
+        metadata::TransformPluginMetadataContext,
+        serialized::{PluginSerializedBytes, VersionedSerializable},
+    };
+    use swc_ecma_ast::Program;
+
+    let metadata_context = Arc::new(TransformPluginMetadataContext::new(
+        Some(path.display().to_string()),
+        if debug { "development" } else { "production" }.to_owned(),
+        None,
+    ));
+
+    let cache = PLUGIN_MODULE_CACHE
+        .inner
+        .get()
+        .expect("Cache should be available");
+
+    swc_plugin_proxy::COMMENTS.set(
+        &swc_plugin_proxy::HostCommentsStorage {
+            inner: Some(comments.clone()),
+        },
+        || {
+            let program = VersionedSerializable::new(Program::Module(module));
+            let mut serialized = PluginSerializedBytes::try_serialize(&program)?;
+
+            for PluginConfig(name, config) in plugins {
+                let (plugin_module, runtime) = {
+                    let cache = cache.lock();
+                    let plugin_module = cache.get(name).expect("plugin module should be loaded");
+                    let runtime = swc_plugin_runner::wasix_runtime::build_wasi_runtime(
+                        cache.get_fs_cache_root().map(Into::into),
+                    );
+
+                    (plugin_module, runtime)
+                };
+
+                let mut executor = swc_plugin_runner::create_plugin_transform_executor(
+                    &cm,
+                    &unresolved_mark,
+                    &metadata_context,
+                    plugin_module,
+                    Some(config.clone()),
+                    runtime,
+                );
+
+                serialized = executor
+                    .transform(&serialized, Some(true))
+                    .with_context(|| {
+                        format!("failed to invoke `{name}` as js transform plugin on {path:?}")
+                    })?;
+            }
+
+            Ok(serialized
+                .deserialize::<Program>()?
+                .into_inner()
+                .expect_module())
+        },
+    )
+}
+
+#[cfg(not(feature = "plugin"))]
+pub fn apply_plugins(
+    _plugins: &[PluginConfig],
+    _path: &Path,
+    module: Module,
+    _comments: &SingleThreadedComments,
+    _unresolved_mark: Mark,
+    _cm: Lrc<SourceMap>,
+    _debug: bool,
+) -> anyhow::Result<Module> {
+    Ok(module)
+}
@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use glob::Pattern;
+use serde_json::Value;
+
+/// The npm, yarn or pnpm workspace a project is part of
+#[derive(Debug, Default, Clone)]
+pub struct Workspace {
+    pub root: PathBuf,
+    /// Package names, and their directories
+    pub packages: Vec<(String, PathBuf)>,
+}
+
+impl Workspace {
+    /// Walk up from `root` to the first `pnpm-workspace.yaml`, or `package.json` with `workspaces`
+    pub fn find(root: &Path) -> Option<Self> {
+        root.ancestors().find_map(|dir| {
+            let patterns = pnpm_patterns(dir).or_else(|| npm_patterns(dir))?;
+            Some(Self::load(dir, &patterns))
+        })
+    }
+
+    fn load(root: &Path, patterns: &[String]) -> Self {
+        let excluded = patterns
+            .iter()
+            .filter_map(|p| p.strip_prefix('!'))
+            .filter_map(|p| Pattern::new(&root.join(p).display().to_string()).ok())
+            .collect::<Vec<_>>();
+
+        let mut packages = vec![];
+        for pattern in patterns.iter().filter(|p| !p.starts_with('!')) {
+            let Ok(dirs) = glob::glob(&root.join(pattern).display().to_string()) else {
+                continue;
+            };
+
+            for dir in dirs.flatten() {
+                if excluded.iter().any(|e| e.matches_path(&dir)) {
+                    continue;
+                }
+
+                let name = fs::read_to_string(dir.join("package.json"))
+                    .ok()
+                    .and_then(|json| serde_json::from_str::<Value>(&json).ok())
+                    .and_then(|json| json.get("name")?.as_str().map(ToOwned::to_owned));
+
+                if let Some(name) = name {
+                    packages.push((name, fs::canonicalize(&dir).unwrap_or(dir)));
+                }
+            }
+        }
+
+        Self {
+            root: root.to_owned(),
+            packages,
+        }
+    }
+}
+
+/// Only the `packages` list of `pnpm-workspace.yaml` is made of `- item` lines
+fn pnpm_patterns(dir: &Path) -> Option<Vec<String>> {
+    let yaml = fs::read_to_string(dir.join("pnpm-workspace.yaml")).ok()?;
+
+    let patterns = yaml
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix('-'))
+        .map(|p| p.trim().trim_matches(|c| c == '\'' || c == '"').to_owned())
+        .collect();
+
+    Some(patterns)
+}
+
+/// `workspaces` is either a list, or yarn's `{ "packages": [] }`
+fn npm_patterns(dir: &Path) -> Option<Vec<String>> {
+    let json = fs::read_to_string(dir.join("package.json")).ok()?;
+    let json = serde_json::from_str::<Value>(&json).ok()?;
+
+    let workspaces = json.get("workspaces")?;
+    let workspaces = workspaces.get("packages").unwrap_or(workspaces);
+
+    let patterns = workspaces
+        .as_array()?
+        .iter()
+        .filter_map(|p| p.as_str().map(ToOwned::to_owned))
+        .collect();
+
+    Some(patterns)
+}
+
+/// Whether a file belongs to a dependency, rather than the project itself. Symlinks are followed,
+/// so workspace packages linked into `node_modules` count as the project's own code
+pub fn is_dependency(path: &Path) -> bool {
+    let real = fs::canonicalize(path);
+    let path = real.as_deref().unwrap_or(path);
+
+    path.components()
+        .any(|c| matches!(c, Component::Normal(c) if c == "node_modules"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn reads_pnpm_patterns() {
+        let dir = TempDir::new(
+            "workspace_pnpm",
+            &[(
+                "pnpm-workspace.yaml",
+                "packages:\n  - 'packages/*'\n  - \"apps/web\"\n  -   '!**/test/**'\n",
+            )],
+        );
+
+        assert_eq!(
+            pnpm_patterns(&dir.0).unwrap(),
+            ["packages/*", "apps/web", "!**/test/**"]
+        );
+    }
+
+    #[test]
+    fn reads_npm_and_yarn_patterns() {
+        let npm = TempDir::new(
+            "workspace_npm",
+            &[("package.json", r#"{ "workspaces": ["packages/*"] }"#)],
+        );
+        let yarn = TempDir::new(
+            "workspace_yarn",
+            &[(
+                "package.json",
+                r#"{ "workspaces": { "packages": ["apps/*"], "nohoist": ["**/react"] } }"#,
+            )],
+        );
+        let none = TempDir::new(
+            "workspace_none",
+            &[("package.json", r#"{ "name": "app" }"#)],
+        );
+
+        assert_eq!(npm_patterns(&npm.0).unwrap(), ["packages/*"]);
+        assert_eq!(npm_patterns(&yarn.0).unwrap(), ["apps/*"]);
+        assert!(npm_patterns(&none.0).is_none());
+    }
+
+    #[test]
+    fn finds_packages_from_a_nested_project() {
+        let dir = TempDir::new(
+            "workspace_find",
+            &[
+                (
+                    "package.json",
+                    r#"{ "workspaces": ["packages/*", "!packages/ignored"] }"#,
+                ),
+                ("packages/ui/package.json", r#"{ "name": "@app/ui" }"#),
+                ("packages/ignored/package.json", r#"{ "name": "ignored" }"#),
+                ("packages/unnamed/package.json", "{}"),
+                ("packages/web/static/index.js", ""),
+            ],
+        );
+
+        let workspace = Workspace::find(&dir.0.join("packages/web/static")).unwrap();
+        assert_eq!(workspace.root, dir.0);
+        assert_eq!(
+            workspace.packages,
+            [("@app/ui".to_owned(), dir.0.join("packages/ui"))]
+        );
+    }
+
+    #[test]
+    fn node_modules_are_dependencies() {
+        assert!(is_dependency(Path::new("/app/node_modules/react/index.js")));
+        assert!(is_dependency(Path::new(
+            "/app/node_modules/@scope/pkg/node_modules/dep.js"
+        )));
+        assert!(!is_dependency(Path::new("/app/src/node_modules.js")));
+        assert!(!is_dependency(Path::new("/app/src/index.js")));
+    }
+}
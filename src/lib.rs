@@ -0,0 +1,23 @@
+//! Bundle and compile an es module project into one distributable js file.
+//!
+//! The [`Trunk`](https://github.com/thedodd/trunk/) post-build hook is a thin wrapper around this crate,
+//! but the bundler can also be used on its own, e.g. from a `build.rs`:
+//!
+//! ```no_run
+//! use trunk_bundler::js_bundler::BundleBuilder;
+//!
+//! let output = BundleBuilder::new("static/scripts")
+//!     .entries(["app"])
+//!     .debug(false)
+//!     .build()
+//!     .expect("Failed to bundle js");
+//!
+//! std::fs::write(&output.manifest.output, &output.code).unwrap();
+//! ```
+
+pub mod asset_manager;
+pub mod errors;
+mod helpers;
+pub mod hmr;
+pub mod js_bundler;
+pub mod pipelines;
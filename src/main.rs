@@ -1,14 +1,15 @@
-mod asset_manager;
-mod errors;
-mod helpers;
-mod js_bundler;
-mod pipelines;
-
-use pipelines::PipelineProcessor;
+use trunk_bundler::pipelines::PipelineProcessor;
 
 use std::{env, path::Path};
 
 fn main() {
+    // `trunk_bundler hmr [index.html]` runs the hot reload server next to `trunk serve`
+    if env::args().nth(1).as_deref() == Some("hmr") {
+        let html_file = env::args().nth(2).unwrap_or("index.html".to_owned());
+        trunk_bundler::hmr::serve(html_file).expect("Failed to run hmr server");
+        return;
+    }
+
     let is_debug = env::var("TRUNK_PROFILE").unwrap() == "debug";
 
     // we need to get the html file from the staging directory, not from the source dir
@@ -18,5 +19,10 @@ fn main() {
     let staging_dir = Path::new(&staging_dir);
     let html_file = staging_dir.join(html_file);
 
-    PipelineProcessor::new(html_file, is_debug).run();
+    // `--verify-reproducible` builds twice, and fails if the outputs differ
+    let verify = env::args().any(|a| a == "--verify-reproducible");
+
+    PipelineProcessor::new(html_file, is_debug)
+        .verify_reproducible(verify)
+        .run();
 }
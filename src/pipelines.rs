@@ -1,18 +1,26 @@
 mod js;
 
-use std::{fs, path::PathBuf, sync::Mutex};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::Mutex,
+};
 
 use js::process_js;
+pub(crate) use js::{js_projects, JsProject};
 use minify_html::Cfg;
 
 use crate::asset_manager::{AssetManager, AssetType};
 use nipper::Document;
+use walkdir::WalkDir;
 
 pub struct PipelineProcessor {
     debug: bool,
     document: Document,
     asset_manager: Mutex<AssetManager>,
     html_file: PathBuf,
+    verify_reproducible: bool,
 }
 
 impl PipelineProcessor {
@@ -24,22 +32,94 @@ impl PipelineProcessor {
             document: Document::from(&html),
             asset_manager: Mutex::new(AssetManager::new()),
             html_file,
+            verify_reproducible: false,
         }
     }
 
+    /// Build everything a second time, in a fresh process, and fail if any output differs
+    pub fn verify_reproducible(mut self, verify: bool) -> Self {
+        self.verify_reproducible = verify;
+        self
+    }
+
     pub fn run(&mut self) {
         self.process_pipelines();
+        if self.verify_reproducible {
+            self.verify();
+        }
         self.finalize();
     }
 
+    /// Runs the build again in a fresh process, so that nothing is reused from this one, with a
+    /// scratch staging dir for it to write to
+    fn verify(&self) {
+        let staging_dir = env::var("TRUNK_STAGING_DIR").unwrap();
+        let staging_dir = Path::new(&staging_dir);
+        let html_name = self.html_file.file_name().unwrap();
+
+        let dir = env::temp_dir().join(format!("trunk_bundler_verify_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create the staging dir of the second build");
+        fs::copy(&self.html_file, dir.join(html_name)).expect("Failed to copy the html file");
+
+        let exe = env::current_exe().expect("Failed to find the current executable");
+        let status = Command::new(exe)
+            .env("TRUNK_STAGING_DIR", &dir)
+            .status()
+            .expect("Failed to run the second build");
+        if !status.success() {
+            let _ = fs::remove_dir_all(&dir);
+            panic!("The second build failed with {status}");
+        }
+
+        let mut outputs = self
+            .asset_manager
+            .lock()
+            .unwrap()
+            .assets
+            .iter()
+            .map(|asset| {
+                let path = asset.destination.strip_prefix(staging_dir).unwrap();
+                let data = match &asset.data {
+                    AssetType::Memory(data) => data.clone(),
+                    AssetType::Path(file) => fs::read(file).unwrap_or_default(),
+                };
+                (path.to_owned(), data)
+            })
+            .collect::<Vec<_>>();
+        outputs.push((html_name.into(), self.html()));
+
+        let mut differing = outputs
+            .iter()
+            .filter(|(path, data)| fs::read(dir.join(path)).ok().as_ref() != Some(data))
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        differing.extend(
+            WalkDir::new(&dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.path().strip_prefix(&dir).unwrap().to_owned())
+                .filter(|path| !outputs.iter().any(|(p, _)| p == path)),
+        );
+        let _ = fs::remove_dir_all(&dir);
+
+        if !differing.is_empty() {
+            let files = differing
+                .iter()
+                .map(|f| format!("\n  {}", staging_dir.join(f).display()))
+                .collect::<String>();
+            panic!("The build is not reproducible, these files differ between two builds:{files}");
+        }
+    }
+
     fn process_pipelines(&mut self) {
         process_js(&mut self.document, &self.asset_manager, self.debug);
     }
 
-    fn finalize(&self) {
-        let mut asset_manager = self.asset_manager.lock().unwrap();
-
-        let html = if !self.debug {
+    /// The processed html, minified in release builds
+    fn html(&self) -> Vec<u8> {
+        if !self.debug {
             let mut cfg = Cfg::spec_compliant();
             // just in case
             cfg.minify_css = true;
@@ -49,7 +129,12 @@ impl PipelineProcessor {
             minify_html::minify((*self.document.html()).as_bytes(), &cfg)
         } else {
             (*self.document.html()).into()
-        };
+        }
+    }
+
+    fn finalize(&self) {
+        let html = self.html();
+        let mut asset_manager = self.asset_manager.lock().unwrap();
 
         // add processed html file now
         let asset = AssetType::Memory(html);
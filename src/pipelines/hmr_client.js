@@ -0,0 +1,67 @@
+(() => {
+    const hmr = (window.__TRUNK_HMR__ = window.__TRUNK_HMR__ || {});
+
+    // set up by the hmr prelude in the bundle, once react-refresh is loaded
+    hmr.runtime = hmr.runtime || null;
+
+    window.$RefreshReg$ = (type, id) => hmr.runtime && hmr.runtime.register(type, id);
+    window.$RefreshSig$ = () =>
+        hmr.runtime ? hmr.runtime.createSignatureFunctionForTransform() : (type) => type;
+
+    // namespaces of the modules the bundle imports, by id. Hot updates import them from here,
+    // so they share the page's react instead of bringing their own
+    hmr.modules = hmr.modules || {};
+    hmr.register = (id, namespace) => {
+        hmr.modules[id] = namespace;
+    };
+    hmr.import = (id) => {
+        if (!(id in hmr.modules)) {
+            throw new Error(`${id} is not loaded in the page`);
+        }
+        return hmr.modules[id];
+    };
+    // commonjs modules are their own default export
+    hmr.importDefault = (id) => {
+        const namespace = hmr.import(id);
+        return "default" in namespace ? namespace.default : namespace;
+    };
+
+    const load = async (modules) => {
+        for (const { id, code } of modules) {
+            const url = URL.createObjectURL(new Blob([code], { type: "text/javascript" }));
+            try {
+                hmr.modules[id] = await import(url);
+            } finally {
+                URL.revokeObjectURL(url);
+            }
+        }
+    };
+
+    const connect = () => {
+        const ws = new WebSocket(`ws://${location.hostname}:__TRUNK_HMR_PORT__`);
+
+        ws.onmessage = async (event) => {
+            const message = JSON.parse(event.data);
+
+            if (message.type === "update") {
+                try {
+                    await load(message.modules);
+                    hmr.runtime.performReactRefresh();
+                    console.info("[hmr] updated", message.files);
+                } catch (e) {
+                    console.error("[hmr] update failed, reloading", e);
+                    location.reload();
+                }
+            } else if (message.type === "reload") {
+                // not hot swappable, Trunk rebuilds and reloads the page
+                console.info("[hmr] not accepted, waiting for Trunk to reload", message.files);
+            } else if (message.type === "error") {
+                console.error("[hmr]", message.message);
+            }
+        };
+
+        ws.onclose = () => setTimeout(connect, 1000);
+    };
+
+    connect();
+})();
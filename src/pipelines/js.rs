@@ -2,95 +2,377 @@ use std::path::PathBuf;
 use std::{env, path::Path};
 use std::{fs, sync::Mutex};
 
-use nipper::Document;
+use nipper::{Document, Selection};
 use rayon::prelude::*;
 
 use crate::{
     asset_manager::AssetManager,
-    js_bundler::{compile_js, generate_config},
+    js_bundler::{
+        compile_js, generate_config, CargoOptions, HmrOptions, LegalComments, Manifest,
+        MinifyLevel, ModuleType, OutputConfig, SwcrcConfig,
+    },
 };
 
-#[derive(Debug)]
-struct JsProject {
-    js_root: PathBuf,
-    modules: Vec<String>,
-    external_modules: Option<Vec<String>>,
-    output: PathBuf,
-    selection: usize,
-}
-
-pub fn process_js(document: &mut Document, asset_manager: &Mutex<AssetManager>, debug: bool) {
-    let sel = document.select(r#"link[data-bundler][rel="js"]"#);
-    let mut selections = sel.iter().collect::<Vec<_>>();
+const HMR_CLIENT: &str = include_str!("hmr_client.js");
+const ES_MODULE_SHIMS: &str =
+    "https://ga.jspm.io/npm:es-module-shims@1.8.0/dist/es-module-shims.js";
 
-    let source_dir = env::var("TRUNK_SOURCE_DIR").unwrap();
-    let source_dir = Path::new(&source_dir);
-
-    let staging_dir = env::var("TRUNK_STAGING_DIR").unwrap();
-    let staging_dir = Path::new(&staging_dir);
+#[derive(Debug, Clone)]
+pub(crate) struct JsProject {
+    pub js_root: PathBuf,
+    /// The `data-config` file, or the default config, with the minifier attributes applied.
+    /// Other settings it has are used when their attribute isn't set
+    pub config: SwcrcConfig,
+    pub modules: Vec<String>,
+    pub external_modules: Option<Vec<String>>,
+    /// Externals with a `name=url`, for the import map of es bundles
+    pub import_map: Vec<(String, String)>,
+    pub module_type: ModuleType,
+    pub output: PathBuf,
+    /// With a `[name]` output, the entries to add a tag for. All of them when `None`
+    pub entries: Option<Vec<String>>,
+    /// Projects of the same group are bundled together, sharing a common chunk
+    pub group: Option<String>,
+    /// Lib folders and files to add. All of them when `None`
+    pub libs: Option<Vec<String>>,
+    pub hmr: Option<HmrOptions>,
+    pub node_shims: bool,
+    pub legal_comments: LegalComments,
+    pub license_file: bool,
+    /// Override `SwcrcConfig::banner` and `footer`
+    pub banner: Option<String>,
+    pub footer: Option<String>,
+    pub external_globals: Option<Vec<(String, String)>>,
+    /// Scripts which load the external globals, in dependency order
+    pub external_scripts: Vec<String>,
+    pub selection: usize,
+}
 
+/// Read the `<link data-bundler>` projects out of the selected tags
+pub(crate) fn js_projects(
+    selections: &[Selection],
+    source_dir: &Path,
+    debug: bool,
+) -> Vec<JsProject> {
     let mut projects = vec![];
     for (i, selection) in selections.iter().enumerate() {
         let href = &*selection.attr("href").expect("`href` attr not found");
         let js_root = fs::canonicalize(source_dir.join(href)).expect("Failed to canonicalize path");
 
+        let mut config = match selection.attr("data-config") {
+            Some(file) => SwcrcConfig::from_file(&js_root.join(&*file), debug)
+                .expect("Failed to read `data-config`"),
+            None => generate_config(debug),
+        };
+
+        if let Some(level) = selection.attr("data-minify") {
+            config.minify_level = match level.trim() {
+                "full" => MinifyLevel::Full,
+                "whitespace-only" => MinifyLevel::WhitespaceOnly,
+                "none" => MinifyLevel::None,
+                l => panic!(
+                    "Unknown `data-minify` {l}, expected `full`, `whitespace-only` or `none`"
+                ),
+            };
+        }
+        if selection.attr("data-drop-console").is_some() {
+            config
+                .drop_console()
+                .expect("`data-drop-console` needs `minify.compress`");
+        }
+        if selection.attr("data-keep-names").is_some() {
+            config.keep_names();
+        }
+        if let Some(file) = selection.attr("data-name-cache") {
+            config.name_cache = Some(PathBuf::from(&*file));
+        }
+        if let Some(regex) = selection.attr("data-mangle-props") {
+            let reserved = selection
+                .attr("data-mangle-reserved")
+                .map(|r| {
+                    r.split_terminator(',')
+                        .map(|p| p.trim().to_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            config
+                .mangle_props(&regex, reserved)
+                .expect("Invalid `data-mangle-props`");
+        }
+        if let Some(file) = selection.attr("data-mangle-report") {
+            config.mangle_report = Some(PathBuf::from(&*file));
+        }
+
+        // without modules, the bundler uses the project's `package.json` entry
         let modules = selection
             .attr("data-modules")
-            .expect("`data-modules` not found");
-        let modules = modules
-            .split_terminator(',')
-            .map(|m| m.trim().to_owned())
-            .collect::<Vec<_>>();
+            .map(|modules| {
+                modules
+                    .split_terminator(',')
+                    .map(|m| m.trim().to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
 
-        let external_modules = selection.attr("data-external-modules").map(|s| {
-            s.split_terminator(',')
-                .map(|m| m.trim().to_owned())
-                .collect::<Vec<_>>()
-        });
+        // externals are either a module name, or `name=url`
+        let mut import_map = vec![];
+        let external_modules = selection
+            .attr("data-external-modules")
+            .map(|s| {
+                s.split_terminator(',')
+                    .map(|m| match m.split_once('=') {
+                        Some((name, url)) => {
+                            import_map.push((name.trim().to_owned(), url.trim().to_owned()));
+                            name.trim().to_owned()
+                        }
+                        None => m.trim().to_owned(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .or(config.external_modules.clone());
+
+        let module_type = match selection.attr("data-module-type").as_deref() {
+            None => config.module_type,
+            Some("iife") => ModuleType::Iife,
+            Some("es") => ModuleType::Es,
+            Some(t) => panic!("Unknown `data-module-type` {t}, expected `es` or `iife`"),
+        };
 
         let output = selection
             .attr("data-output")
             .map(|t| PathBuf::from(&*t))
+            .or(config.output.clone())
             .unwrap_or(PathBuf::from(if debug { "dist.js" } else { "dist.min.js" }));
 
+        let entries = selection.attr("data-entries").map(|s| {
+            s.split_terminator(',')
+                .map(|e| e.trim().to_owned())
+                .collect::<Vec<_>>()
+        });
+
+        let libs = selection
+            .attr("data-libs")
+            .map(|s| {
+                s.split_terminator(',')
+                    .map(|l| l.trim().to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .or(config.libs.clone());
+
+        let group = selection
+            .attr("data-group")
+            .map(|g| g.trim().to_owned())
+            .filter(|g| !g.is_empty());
+
+        // hmr is only for debug builds. The attribute value is an optional port
+        let hmr = selection
+            .attr("data-hmr")
+            .filter(|_| debug)
+            .map(|port| match port.trim() {
+                "" => HmrOptions::default(),
+                port => HmrOptions {
+                    port: port.parse().expect("`data-hmr` is not a valid port"),
+                },
+            })
+            .or(config.hmr.clone().filter(|_| debug));
+
+        let node_shims = selection.attr("data-node-shims").is_some() || config.node_shims;
+
+        let legal_comments = match selection.attr("data-legal-comments").as_deref() {
+            None => config.legal_comments,
+            Some("eof") => LegalComments::Eof,
+            Some("inline") => LegalComments::Inline,
+            Some("external") => LegalComments::External,
+            Some("none") => LegalComments::None,
+            Some(l) => panic!(
+                "Unknown `data-legal-comments` {l}, expected `inline`, `eof`, `external` or `none`"
+            ),
+        };
+        let license_file = selection.attr("data-license-file").is_some() || config.license_file;
+
+        let banner = selection.attr("data-banner").map(|b| b.to_string());
+        let footer = selection.attr("data-footer").map(|f| f.to_string());
+
+        let external_globals = selection
+            .attr("data-external-globals")
+            .map(|s| {
+                s.split_terminator(',')
+                    .map(|m| {
+                        let (module, global) = m
+                            .split_once('=')
+                            .expect("`data-external-globals` entries must be `module=Global`");
+                        (module.trim().to_owned(), global.trim().to_owned())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .or(config
+                .external_globals
+                .clone()
+                .map(|g| g.into_iter().collect()));
+
+        let external_scripts = selection
+            .attr("data-external-scripts")
+            .map(|s| {
+                s.split_terminator(',')
+                    .map(|url| url.trim().to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
         projects.push(JsProject {
             js_root,
+            config,
             modules,
             external_modules,
+            import_map,
+            module_type,
             output,
+            entries,
+            group,
+            libs,
+            hmr,
+            node_shims,
+            legal_comments,
+            license_file,
+            banner,
+            footer,
+            external_globals,
+            external_scripts,
             selection: i,
         });
     }
 
-    let outputs_to_selections = projects
+    projects
+}
+
+pub fn process_js(document: &mut Document, asset_manager: &Mutex<AssetManager>, debug: bool) {
+    let sel = document.select(r#"link[data-bundler][rel="js"]"#);
+    let mut selections = sel.iter().collect::<Vec<_>>();
+
+    let source_dir = env::var("TRUNK_SOURCE_DIR").unwrap();
+    let source_dir = Path::new(&source_dir);
+
+    let staging_dir = env::var("TRUNK_STAGING_DIR").unwrap();
+    let staging_dir = Path::new(&staging_dir);
+
+    let projects = js_projects(&selections, source_dir, debug);
+    let cargo = cargo_options(document, source_dir);
+
+    // there can only be one import map, which has to come before any module script
+    let import_map = projects
         .iter()
-        .map(|p| (p.output.clone(), p.selection))
-        .collect::<Vec<_>>();
+        .filter(|p| p.module_type == ModuleType::Es)
+        .flat_map(|p| p.import_map.iter().cloned())
+        .map(|(name, url)| (name, serde_json::Value::String(url)))
+        .collect::<serde_json::Map<_, _>>();
+    let import_map_shim = selections
+        .iter()
+        .find_map(|s| s.attr("data-import-map-shim"))
+        .map(|url| match url.trim() {
+            "" => ES_MODULE_SHIMS.to_owned(),
+            url => url.to_owned(),
+        });
+    let mut import_map = (!import_map.is_empty()).then(|| {
+        let import_map = serde_json::json!({ "imports": import_map });
+        let shim = import_map_shim
+            .map(|url| format!(r#"<script async src="{url}"></script>"#))
+            .unwrap_or_default();
+        format!(r#"{shim}<script type="importmap">{import_map}</script>"#)
+    });
+
+    // projects of a group are built together, from the settings of the first one
+    let mut builds = Vec::<Vec<JsProject>>::new();
+    for project in projects {
+        let build = builds
+            .iter_mut()
+            .find(|b| project.group.is_some() && b[0].group == project.group);
+
+        match build {
+            Some(build) => {
+                let first = &build[0];
+                if first.js_root != project.js_root || first.module_type != project.module_type {
+                    panic!("Links in a `data-group` need the same `href` and `data-module-type`");
+                }
+                build.push(project);
+            }
+            None => builds.push(vec![project]),
+        }
+    }
 
     // parallelize js compilation
-    projects.into_par_iter().panic_fuse().for_each(|p| {
-        let mut js_config = generate_config(debug);
-        js_config.modules = p.modules;
-        js_config.external_modules = p.external_modules;
-        js_config.output = Some(p.output);
+    let manifests = builds.par_iter().panic_fuse().map(|build| {
+        let p = &build[0];
+        let mut js_config = p.config.clone();
+        js_config.modules = p.modules.clone();
+        js_config.external_modules = p.external_modules.clone();
+        js_config.module_type = p.module_type;
+        js_config.output = Some(p.output.clone());
+        js_config.hmr = p.hmr.clone();
+        js_config.node_shims = p.node_shims;
+        js_config.legal_comments = p.legal_comments;
+        js_config.license_file = p.license_file;
+        js_config.cargo = cargo.clone();
+        if p.banner.is_some() {
+            js_config.banner = p.banner.clone();
+        }
+        if p.footer.is_some() {
+            js_config.footer = p.footer.clone();
+        }
+        js_config.libs = p.libs.clone();
+        js_config.external_globals = p.external_globals.clone().map(|g| g.into_iter().collect());
+
+        // the common chunk of a group goes next to its first output
+        if let Some(group) = &p.group {
+            if build
+                .iter()
+                .any(|p| p.output.to_string_lossy().contains("[name]"))
+            {
+                panic!("Links in a `data-group` can't have a `[name]` output");
+            }
+
+            js_config.output = Some(p.output.with_file_name(format!("{group}.js")));
+            js_config.outputs = Some(
+                build
+                    .iter()
+                    .map(|p| OutputConfig {
+                        output: p.output.clone(),
+                        modules: p.modules.clone(),
+                    })
+                    .collect(),
+            );
+        }
 
         compile_js(
-            p.js_root,
+            p.js_root.clone(),
             staging_dir.to_path_buf(),
             js_config,
             asset_manager,
             debug,
             None,
         )
-        .expect("Failed to compile js");
+        .expect("Failed to compile js")
     });
+    let manifests = manifests.collect::<Vec<_>>();
 
     // replace tags in html
-    for (output, i_selection) in outputs_to_selections.into_iter() {
-        let selection = &mut selections[i_selection];
-        let output = output.display().to_string();
-        // ensure we don't do any double //
-        let output = output.strip_prefix('/').unwrap_or(&output);
+    let mut hmr_client_injected = false;
+    let mut projects = builds
+        .into_iter()
+        .zip(manifests)
+        .flat_map(|(build, manifest)| {
+            build.into_iter().enumerate().map(move |(i, p)| {
+                // the common chunk is linked once, before anything else of the build
+                let outputs = tag_outputs(&p, &manifest, i == 0);
+                let css = manifest.css.clone().filter(|_| i == 0);
+                (p, outputs, css)
+            })
+        })
+        .collect::<Vec<_>>();
+    // in document order, the import map and the hmr client go before the first tag
+    projects.sort_by_key(|(p, ..)| p.selection);
+
+    for (project, outputs, css) in projects {
+        let selection = &mut selections[project.selection];
 
         let public_url = env::var("TRUNK_PUBLIC_URL").unwrap();
         let _async = selection.attr("data-async").map(|_| " async");
@@ -105,14 +387,117 @@ pub fn process_js(document: &mut Document, asset_manager: &Mutex<AssetManager>,
         let _async = _async.unwrap_or("");
         let defer = defer.unwrap_or("");
 
-        if !preload {
-            selection.replace_with_html(format!(
-                r#"<script src="{public_url}{output}"{_async}{defer}></script>"#
-            ));
+        // the hmr client has to be set up before any bundle runs
+        let hmr_client = match project.hmr {
+            Some(hmr) if !hmr_client_injected => {
+                hmr_client_injected = true;
+                let client = HMR_CLIENT.replace("__TRUNK_HMR_PORT__", &hmr.port.to_string());
+                format!("<script>{client}</script>")
+            }
+            _ => String::new(),
+        };
+
+        // globals for external modules have to be loaded before the bundle
+        let external_scripts = project
+            .external_scripts
+            .iter()
+            .map(|url| format!(r#"<script src="{url}"></script>"#))
+            .collect::<String>();
+
+        let is_module = project.module_type == ModuleType::Es;
+        let import_map = if is_module {
+            import_map.take().unwrap_or_default()
         } else {
-            selection.replace_with_html(format!(
-                r#"<link rel="preload" href="{public_url}{output}" as="script" />"#
-            ));
-        }
+            String::new()
+        };
+
+        let module = if is_module { r#" type="module""# } else { "" };
+        let tags = outputs
+            .iter()
+            .map(|(output, is_entry)| {
+                let output = output.display().to_string();
+                // ensure we don't do any double //
+                let output = output.strip_prefix('/').unwrap_or(&output);
+
+                // the common chunk has to run first, so it's never async
+                let _async = if *is_entry { _async } else { "" };
+
+                if !preload {
+                    format!(
+                        r#"<script src="{public_url}{output}"{module}{_async}{defer}></script>"#
+                    )
+                } else if is_module {
+                    format!(r#"<link rel="modulepreload" href="{public_url}{output}" />"#)
+                } else {
+                    format!(r#"<link rel="preload" href="{public_url}{output}" as="script" />"#)
+                }
+            })
+            .collect::<String>();
+
+        // lib stylesheets
+        let styles = css
+            .map(|css| {
+                let css = css.display().to_string();
+                let css = css.strip_prefix('/').unwrap_or(&css).to_owned();
+                format!(r#"<link rel="stylesheet" href="{public_url}{css}" />"#)
+            })
+            .unwrap_or_default();
+
+        selection.replace_with_html(format!(
+            "{styles}{import_map}{hmr_client}{external_scripts}{tags}"
+        ));
+    }
+}
+
+/// The crate and features of Trunk's `<link data-trunk rel="rust">`, for the build globals
+fn cargo_options(document: &Document, source_dir: &Path) -> CargoOptions {
+    let sel = document.select(r#"link[data-trunk][rel="rust"]"#);
+    let Some(link) = sel.iter().next() else {
+        return CargoOptions::default();
+    };
+
+    // features are separated by commas or spaces, like in Trunk
+    let features = link
+        .attr("data-cargo-features")
+        .map(|f| {
+            f.split([',', ' '])
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    CargoOptions {
+        manifest: Some(source_dir.join(link.attr("href").as_deref().unwrap_or(""))),
+        features,
+        all_features: link.attr("data-cargo-all-features").is_some(),
+        no_default_features: link.attr("data-cargo-no-default-features").is_some(),
+    }
+}
+
+/// Files to add a tag for, and whether each is an entry. Split bundles get a tag per entry,
+/// after the common chunk
+fn tag_outputs(project: &JsProject, manifest: &Manifest, first: bool) -> Vec<(PathBuf, bool)> {
+    if manifest.chunks.is_empty() {
+        return vec![(manifest.output.clone(), true)];
     }
+
+    let entries = manifest
+        .chunks
+        .iter()
+        .filter(|(name, output)| match (&project.group, &project.entries) {
+            (Some(_), _) => *output == project.output,
+            (None, Some(entries)) => entries.contains(name),
+            (None, None) => true,
+        })
+        .map(|(_, output)| (output.clone(), true));
+
+    manifest
+        .common
+        .clone()
+        .filter(|_| first)
+        .map(|common| (common, false))
+        .into_iter()
+        .chain(entries)
+        .collect()
 }